@@ -0,0 +1,242 @@
+//! Bank switching.
+//! A 6502 sees 64KB at a time, but cartridges, mappers and single board computers carry far more ROM and RAM than that.
+//! They get at it by splitting a region of the address space into fixed-size windows and letting the program pick,
+//! by writing to a latch, which page (bank) of the big array each window shows. NES mappers, Commodore cartridges and
+//! most homebrew SBCs with more than 32KB of ROM all work like this; they only differ in window sizes and in where the
+//! latches sit.
+//! [Banks] is the storage and window table on its own, for mappers that decode their own registers.
+//! [Banked] puts it on the bus at a base address, with latches at addresses of your choosing.
+use super::BusAccess;
+use std::{collections::HashMap, ops::RangeInclusive};
+
+/// A large array seen through a row of equally sized windows, each showing one bank of the array
+#[derive(Debug, Clone)]
+pub struct Banks {
+    data: Vec<u8>,
+    window: usize,
+    // the bank each window currently shows
+    map: Vec<usize>,
+    writable: bool,
+}
+
+impl Banks {
+    /// `windows` windows of `window` bytes each over `data`. Every window starts out on bank 0.
+    /// `data` is padded with zeros to a whole number of banks, and an empty array gets one bank, so every window
+    /// always has something behind it
+    pub fn new(mut data: Vec<u8>, window: usize, windows: usize) -> Self {
+        assert!(window > 0 && windows > 0);
+        let banks = data.len().div_ceil(window).max(1);
+        data.resize(banks * window, 0);
+        Self {
+            data,
+            window,
+            map: vec![0; windows],
+            writable: false,
+        }
+    }
+
+    /// zero-filled ram of `size` bytes
+    pub fn ram(size: usize, window: usize, windows: usize) -> Self {
+        Self::new(vec![0; size], window, windows).writable()
+    }
+
+    /// lets stores through to the array. Banks are read-only (rom) unless this is called
+    pub fn writable(mut self) -> Self {
+        self.writable = true;
+        self
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    pub fn window_size(&self) -> usize {
+        self.window
+    }
+
+    pub fn windows(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn bank_count(&self) -> usize {
+        self.data.len() / self.window
+    }
+
+    /// the bank window `w` shows
+    pub fn bank(&self, w: usize) -> usize {
+        self.map[w]
+    }
+
+    /// points window `w` at `bank`. Banks past the end wrap around, the way unconnected high address lines make a
+    /// small chip show up several times in a large bank space. Negative numbers count from the last bank, so `-1`
+    /// is the last bank, which is where most boards keep their fixed code
+    pub fn switch(&mut self, w: usize, bank: isize) {
+        let n = self.bank_count() as isize;
+        self.map[w] = bank.rem_euclid(n) as usize;
+    }
+
+    // index into `data` of byte `offset` of the windowed region
+    fn index(&self, offset: usize) -> usize {
+        let w = (offset / self.window) % self.map.len();
+        self.map[w] * self.window + offset % self.window
+    }
+
+    /// loads byte `offset` of the region the windows cover
+    pub fn read(&self, offset: usize) -> u8 {
+        self.data[self.index(offset)]
+    }
+
+    /// stores to byte `offset` of the region. Dropped if the banks are rom
+    pub fn write(&mut self, offset: usize, v: u8) {
+        if self.writable {
+            let i = self.index(offset);
+            self.data[i] = v;
+        }
+    }
+
+    /// the whole array, e.g. for saving battery-backed ram
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+/// [Banks] mapped into the cpu address space at `base`, switched by writes to latch addresses
+#[derive(Debug, Clone)]
+pub struct Banked {
+    banks: Banks,
+    base: u16,
+    // latch address -> the window the value written there selects a bank for
+    latches: HashMap<u16, usize>,
+    // a range every address of which latches a bank for the window given by its offset into the range
+    latch_range: Option<(RangeInclusive<u16>, usize)>,
+}
+
+impl Banked {
+    pub fn new(banks: Banks, base: u16) -> Self {
+        Self {
+            banks,
+            base,
+            latches: HashMap::new(),
+            latch_range: None,
+        }
+    }
+
+    /// writes to `addr` select the bank window `w` shows. The latch shadows whatever is at `addr`, so a latch
+    /// inside a rom region works the way it does on boards that decode writes to rom as bank switches.
+    /// Panics if there is no window `w`
+    pub fn latch(mut self, addr: u16, w: usize) -> Self {
+        assert!(w < self.banks.windows(), "latch for window {} but there are {}", w, self.banks.windows());
+        self.latches.insert(addr, w);
+        self
+    }
+
+    /// every address in `range` is a latch: a write to `range.start() + i` selects the bank of window `first + i`.
+    /// Panics if the range runs past the last window
+    pub fn latch_range(mut self, range: RangeInclusive<u16>, first: usize) -> Self {
+        let len = range.clone().count();
+        assert!(
+            first + len <= self.banks.windows(),
+            "latches for windows {}..{} but there are {}",
+            first,
+            first + len,
+            self.banks.windows()
+        );
+        self.latch_range = Some((range, first));
+        self
+    }
+
+    /// the range of cpu addresses the windows cover
+    pub fn region(&self) -> RangeInclusive<u16> {
+        let len = self.banks.window_size() * self.banks.windows();
+        self.base..=(self.base as usize + len - 1).min(0xffff) as u16
+    }
+
+    pub fn banks(&self) -> &Banks {
+        &self.banks
+    }
+
+    pub fn banks_mut(&mut self) -> &mut Banks {
+        &mut self.banks
+    }
+
+    fn latched(&self, addr: u16) -> Option<usize> {
+        if let Some(&w) = self.latches.get(&addr) {
+            return Some(w);
+        }
+        match &self.latch_range {
+            Some((r, first)) if r.contains(&addr) => Some(first + (addr - r.start()) as usize),
+            _ => None,
+        }
+    }
+}
+
+impl BusAccess for Banked {
+    fn load_u8(&mut self, addr: u16) -> u8 {
+        match addr.checked_sub(self.base) {
+            Some(off) if self.region().contains(&addr) => self.banks.read(off as usize),
+            _ => panic!("Address {} not addressable", addr),
+        }
+    }
+
+    fn store_u8(&mut self, addr: u16, v: u8) {
+        if let Some(w) = self.latched(addr) {
+            self.banks.switch(w, v as isize);
+            return;
+        }
+        match addr.checked_sub(self.base) {
+            Some(off) if self.region().contains(&addr) => self.banks.write(off as usize, v),
+            _ => panic!("Address {} not addressable", addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switch_from_bus_writes() {
+        // 64KB of rom, in 16KB banks, behind two windows at $8000. a latch at $8000 picks the low window's bank
+        let rom: Vec<u8> = (0..4).flat_map(|b| vec![b as u8; 0x4000]).collect();
+        let mut bus = Banked::new(Banks::new(rom, 0x4000, 2), 0x8000).latch(0x8000, 0);
+        bus.banks_mut().switch(1, -1);
+        assert_eq!(bus.load_u8(0x8000), 0);
+        assert_eq!(bus.load_u8(0xffff), 3);
+
+        bus.store_u8(0x8000, 2);
+        assert_eq!(bus.load_u8(0xbfff), 2);
+        // only two bits of bank number are wired
+        bus.store_u8(0x8000, 5);
+        assert_eq!(bus.load_u8(0x8000), 1);
+        // rom ignores other writes
+        bus.store_u8(0x9000, 0xaa);
+        assert_eq!(bus.load_u8(0x9000), 1);
+    }
+
+    #[test]
+    fn latch_range() {
+        // NSF-style: $5FF8-$5FFF pick the banks of the eight 4KB windows at $8000
+        let rom: Vec<u8> = (0..16).flat_map(|b| vec![b as u8; 0x1000]).collect();
+        let mut bus = Banked::new(Banks::new(rom, 0x1000, 8), 0x8000).latch_range(0x5ff8..=0x5fff, 0);
+        bus.store_u8(0x5ff9, 7);
+        bus.store_u8(0x5fff, 12);
+        assert_eq!(bus.load_u8(0x9000), 7);
+        assert_eq!(bus.load_u8(0xf000), 12);
+    }
+
+    #[test]
+    #[should_panic]
+    fn latch_past_the_windows() {
+        Banked::new(Banks::new(vec![0; 0x8000], 0x4000, 2), 0x8000).latch(0x8000, 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn latch_range_past_the_windows() {
+        Banked::new(Banks::new(vec![0; 0x8000], 0x1000, 8), 0x8000).latch_range(0x5ff8..=0x5fff, 1);
+    }
+}
@@ -0,0 +1,343 @@
+//! Per-address access statistics.
+//! [Profiled] wraps a bus and counts the reads, writes and opcode fetches that reach every one of the 65536 addresses.
+//! The counts tell which zero page locations are hot, which ones are never touched (and so free for something else),
+//! and which code never runs. They can be dumped as CSV, drawn as a 256x256 image where each row is a page, or browsed
+//! page by page in the terminal with [HeatView].
+use super::BusAccess;
+use crate::image;
+use cursive::{
+    event::{Event, EventResult, Key},
+    theme::{Color, ColorStyle},
+    Printer, Vec2, View,
+};
+use std::{
+    error::Error,
+    fs::OpenOptions,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+const ADDRS: usize = 0x10000;
+
+/// The number of accesses one address has seen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub reads: u32,
+    pub writes: u32,
+    pub execs: u32,
+}
+
+/// A bus that counts every access before passing it on to `inner`
+#[derive(Debug)]
+pub struct Profiled<B: BusAccess> {
+    inner: B,
+    reads: Vec<u32>,
+    writes: Vec<u32>,
+    execs: Vec<u32>,
+}
+
+impl<B: BusAccess + Default> Default for Profiled<B> {
+    fn default() -> Self {
+        Self::new(B::default())
+    }
+}
+
+impl<B: BusAccess> Profiled<B> {
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            reads: vec![0; ADDRS],
+            writes: vec![0; ADDRS],
+            execs: vec![0; ADDRS],
+        }
+    }
+
+    pub fn counts(&self, addr: u16) -> Counts {
+        let i = addr as usize;
+        Counts {
+            reads: self.reads[i],
+            writes: self.writes[i],
+            execs: self.execs[i],
+        }
+    }
+
+    /// zeroes every counter
+    pub fn reset(&mut self) {
+        for c in [&mut self.reads, &mut self.writes, &mut self.execs] {
+            c.iter_mut().for_each(|n| *n = 0);
+        }
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut B {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    /// one `addr,reads,writes,execs` line per address that saw any access, after a header line
+    pub fn write_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "addr,reads,writes,execs")?;
+        for addr in 0..ADDRS {
+            let c = self.counts(addr as u16);
+            if c != Counts::default() {
+                writeln!(w, "{:04X},{},{},{}", addr, c.reads, c.writes, c.execs)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// the 256x256 heatmap as a PPM. See [Self::heatmap]
+    pub fn write_ppm<W: Write>(&self, w: W) -> io::Result<()> {
+        image::write_ppm(w, 256, 256, &self.heatmap())
+    }
+
+    /// the 256x256 heatmap as a PNG. See [Self::heatmap]
+    pub fn write_png<W: Write>(&self, w: W) -> io::Result<()> {
+        image::write_png(w, 256, 256, &self.heatmap())
+    }
+
+    /// writes the statistics to `path`, in the format its extension asks for: `csv`, `ppm` or `png`
+    pub fn dump<T: AsRef<Path>>(&self, path: T) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let f = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let mut w = BufWriter::new(f);
+        match ext.to_ascii_lowercase().as_str() {
+            "csv" => self.write_csv(&mut w)?,
+            "ppm" => self.write_ppm(&mut w)?,
+            "png" => self.write_png(&mut w)?,
+            _ => {
+                return Err(Box::new(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "heatmap can only be dumped as .csv, .ppm or .png",
+                )))
+            }
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// RGB pixels of a 256x256 image of the address space: row is the page (high byte), column the low byte.
+    /// Reads go in the blue channel, writes in red and execs in green, each on a log scale against the busiest
+    /// address of its kind, so zero page variables show up as a magenta strip across the top and code as green
+    pub fn heatmap(&self) -> Vec<u8> {
+        let scale = |c: &[u32]| {
+            let max = c.iter().copied().max().unwrap_or(0);
+            let den = (max as f64).ln_1p();
+            move |n: u32| {
+                if n == 0 {
+                    0
+                } else {
+                    // anything touched at all gets at least a little colour, so single accesses stay visible
+                    (48.0 + 207.0 * (n as f64).ln_1p() / den) as u8
+                }
+            }
+        };
+        let (r, g, b) = (scale(&self.writes), scale(&self.execs), scale(&self.reads));
+        let mut px = Vec::with_capacity(ADDRS * 3);
+        for i in 0..ADDRS {
+            px.extend_from_slice(&[r(self.writes[i]), g(self.execs[i]), b(self.reads[i])]);
+        }
+        px
+    }
+
+    /// a snapshot of the counters for browsing in the terminal
+    pub fn view(&self) -> HeatView {
+        HeatView {
+            reads: self.reads.clone(),
+            writes: self.writes.clone(),
+            execs: self.execs.clone(),
+            page: 0,
+            kind: Kind::All,
+        }
+    }
+}
+
+impl<B: BusAccess> BusAccess for Profiled<B> {
+    fn load_u8(&mut self, addr: u16) -> u8 {
+        let n = &mut self.reads[addr as usize];
+        *n = n.saturating_add(1);
+        self.inner.load_u8(addr)
+    }
+
+    fn store_u8(&mut self, addr: u16, v: u8) {
+        let n = &mut self.writes[addr as usize];
+        *n = n.saturating_add(1);
+        self.inner.store_u8(addr, v);
+    }
+
+    fn fetch_u8(&mut self, addr: u16) -> u8 {
+        let n = &mut self.execs[addr as usize];
+        *n = n.saturating_add(1);
+        self.inner.fetch_u8(addr)
+    }
+
+    fn sync_pc(&mut self, pc: u16) {
+        self.inner.sync_pc(pc);
+    }
+
+    fn take_break(&mut self) -> Option<super::watch::WatchHit> {
+        self.inner.take_break()
+    }
+
+    fn tick(&mut self) {
+        self.inner.tick();
+    }
+
+    fn irq(&self) -> bool {
+        self.inner.irq()
+    }
+
+    fn nmi(&mut self) -> bool {
+        self.inner.nmi()
+    }
+
+    fn dma(&mut self) -> u64 {
+        self.inner.dma()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    All,
+    Reads,
+    Writes,
+    Execs,
+}
+
+/// Shows one page of a [Profiled] snapshot as a 16x16 grid of counts, shaded by how busy each address is.
+/// Left/right (or page up/down) move between pages, `z` jumps back to the zero page and `m` cycles through showing
+/// all accesses, reads only, writes only and execs only
+pub struct HeatView {
+    reads: Vec<u32>,
+    writes: Vec<u32>,
+    execs: Vec<u32>,
+    page: u8,
+    kind: Kind,
+}
+
+impl HeatView {
+    fn count(&self, addr: usize) -> u32 {
+        match self.kind {
+            Kind::All => self.reads[addr]
+                .saturating_add(self.writes[addr])
+                .saturating_add(self.execs[addr]),
+            Kind::Reads => self.reads[addr],
+            Kind::Writes => self.writes[addr],
+            Kind::Execs => self.execs[addr],
+        }
+    }
+}
+
+// three characters wide at most
+fn short(n: u32) -> String {
+    match n {
+        0..=999 => n.to_string(),
+        1_000..=99_999 => format!("{}k", n / 1_000),
+        100_000..=999_999 => format!(".{}M", n / 100_000),
+        _ => format!("{}M", (n / 1_000_000).min(99)),
+    }
+}
+
+// black, through red and yellow, to white
+fn ramp(t: f64) -> Color {
+    let v = (t * 3.0 * 255.0) as u32;
+    let (r, g, b) = (v.min(255), v.saturating_sub(255).min(255), v.saturating_sub(510).min(255));
+    Color::Rgb(r as u8, g as u8, b as u8)
+}
+
+impl View for HeatView {
+    fn draw(&self, printer: &Printer) {
+        let base = (self.page as usize) << 8;
+        let max = (0..0x100).map(|i| self.count(base + i)).max().unwrap_or(0);
+        let kind = match self.kind {
+            Kind::All => "all accesses",
+            Kind::Reads => "reads",
+            Kind::Writes => "writes",
+            Kind::Execs => "execs",
+        };
+        printer.print((0, 0), &format!("page ${:02X}: {}", self.page, kind));
+        for row in 0..16 {
+            let y = row + 1;
+            printer.print((0, y), &format!("${:02X}{:X}0", self.page, row));
+            for col in 0..16 {
+                let n = self.count(base + row * 16 + col);
+                let t = if n == 0 {
+                    0.0
+                } else {
+                    (n as f64).ln_1p() / (max as f64).ln_1p()
+                };
+                let fg = if t > 0.6 { Color::Rgb(0, 0, 0) } else { Color::Rgb(255, 255, 255) };
+                printer.with_color(ColorStyle::new(fg, ramp(t)), |p| {
+                    p.print((6 + col * 4, y), &format!("{:>3} ", short(n)));
+                });
+            }
+        }
+    }
+
+    fn required_size(&mut self, _constraint: Vec2) -> Vec2 {
+        Vec2::new(6 + 16 * 4, 17)
+    }
+
+    fn on_event(&mut self, event: Event) -> EventResult {
+        match event {
+            Event::Key(Key::Left) | Event::Key(Key::PageUp) => self.page = self.page.wrapping_sub(1),
+            Event::Key(Key::Right) | Event::Key(Key::PageDown) => self.page = self.page.wrapping_add(1),
+            Event::Char('z') => self.page = 0,
+            Event::Char('m') => {
+                self.kind = match self.kind {
+                    Kind::All => Kind::Reads,
+                    Kind::Reads => Kind::Writes,
+                    Kind::Writes => Kind::Execs,
+                    Kind::Execs => Kind::All,
+                }
+            }
+            _ => return EventResult::Ignored,
+        }
+        EventResult::Consumed(None)
+    }
+
+    fn take_focus(&mut self, _source: cursive::direction::Direction) -> Result<EventResult, cursive::view::CannotFocus> {
+        Ok(EventResult::Consumed(None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::six502::ram::Ram;
+
+    #[test]
+    fn counts_and_csv() {
+        let mut bus = Profiled::new(Ram::new());
+        bus.store_u8(0x0010, 1);
+        bus.load_u8(0x0010);
+        bus.load_u8(0x0010);
+        bus.fetch_u8(0x0200);
+        assert_eq!(
+            bus.counts(0x0010),
+            Counts {
+                reads: 2,
+                writes: 1,
+                execs: 0
+            }
+        );
+
+        let mut csv = Vec::new();
+        bus.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "addr,reads,writes,execs\n0010,2,1,0\n0200,0,0,1\n"
+        );
+    }
+}
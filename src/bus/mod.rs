@@ -0,0 +1,804 @@
+use crate::nes::{
+    apu::Apu,
+    audio::AudioOut,
+    cartridge::{Cartridge, CartridgeError},
+    controller::ControllerPort,
+    mapper::{self, Mapper, Unplugged},
+    ppu::Ppu,
+    region::Region,
+};
+
+use self::shadow::{Shadow, UninitRead};
+use self::watch::WatchHit;
+use super::six502::ram::Ram;
+use std::{
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    ops::{Deref, DerefMut},
+    path::Path,
+};
+
+pub(crate) mod bank;
+pub(crate) mod heat;
+pub(crate) mod shadow;
+pub(crate) mod watch;
+
+pub trait BusAccess {
+    fn load_u8(&mut self, addr: u16) -> u8 ;
+    fn store_u8(&mut self, addr: u16, v: u8);
+
+    /// called by the cpu before it fetches each opcode, with the address of that opcode.
+    /// Lets anything on the bus that cares (e.g. the uninitialised-read [Shadow]) attribute accesses to an instruction
+    fn sync_pc(&mut self, _pc: u16) {}
+
+    /// loads an opcode. Same as a load as far as memory is concerned, but lets the bus tell instruction fetches apart
+    fn fetch_u8(&mut self, addr: u16) -> u8 {
+        self.load_u8(addr)
+    }
+
+    /// asked by the cpu at the end of every instruction. A bus that wants execution to stop (e.g. a [watch::Watched]
+    /// whose watchpoint triggered) returns what happened and the cpu hands it to the host
+    fn take_break(&mut self) -> Option<WatchHit> {
+        None
+    }
+
+    /// called once for every cpu cycle: right after the access on it, or for the cycles with no access, once the
+    /// instruction's accesses are done. Devices that count time (mappers, the ppu and the apu) are clocked from here
+    fn tick(&mut self) {}
+
+    /// the level of the cpu's IRQ line, true while some device is pulling it low. The cpu looks at it between
+    /// instructions and takes the interrupt unless the interrupt disable flag is set
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// whether the cpu's NMI line has gone low since the cpu last asked. NMI is edge triggered, so unlike [Self::irq]
+    /// this reports (and forgets) the edge rather than the level. The cpu takes it before the next instruction
+    fn nmi(&mut self) -> bool {
+        false
+    }
+
+    /// called by the cpu after every instruction. If the instruction started a DMA, the bus runs it now, clocking
+    /// itself while the cpu is halted, and returns how many cycles that took
+    fn dma(&mut self) -> u64 {
+        0
+    }
+}
+
+
+#[derive(Debug)]
+pub struct Mem {
+    zp: [u8; 0x100],
+    stack: [u8; 0x100],
+    x: Vec<u8>, // 65018 max. unreserved. contaains program and unused
+    // At the high end of memory, the last six bytes of the last page (page 255) of
+    // memory are used by the hardware to contain special addresses.
+    //https://people.cs.umass.edu/~verts/cmpsci201/spr_2004/Lecture_02_2004-01-30_The_6502_processor.pdf
+    // IRQ, NMI, RESET. each two bytes each
+    special: [u8; 0x06],
+    // when enabled, tracks which of the zero page and stack bytes have been written
+    shadow: Option<Shadow>,
+}
+const MEM_SIZE: usize = 1024 * 64;
+const MAX_PROG: usize = 65018;
+
+impl Default for Mem {
+    fn default() -> Self {
+        Self {
+            zp: [0u8; 256],
+            stack: [0u8; 256],
+            x: Default::default(),
+            special: Default::default(),
+            shadow: None,
+        }
+    }
+}
+
+impl Mem {
+    pub fn open<T: AsRef<Path>>(path: T) -> Result<Self, Box<dyn Error>> {
+        let b = fs::read(path)?;
+        if b.len() > MAX_PROG {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Program larger than 652 allows",
+            )));
+        };
+
+        Ok(Self {
+            zp: [0u8; 0x100],
+            stack: [0u8; 0x100],
+            x: b,
+            special: [0u8; 6],
+            shadow: None,
+        })
+    }
+
+    /// starts tracking reads of the zero page and stack that happen before the location is first written.
+    /// Everything written so far counts as uninitialised, so call this before the program runs
+    pub fn enable_shadow(&mut self) {
+        // zero page and stack, one after the other
+        self.shadow = Some(Shadow::new(0x200));
+    }
+
+    /// reads of never-written ram seen since the shadow was enabled. Empty if it never was
+    pub fn uninit_reads(&self) -> &[UninitRead] {
+        self.shadow.as_ref().map_or(&[], |s| s.reads())
+    }
+
+    pub fn take_uninit_reads(&mut self) -> Vec<UninitRead> {
+        self.shadow.as_mut().map_or_else(Vec::new, |s| s.take_reads())
+    }
+
+    pub(super) fn load_zp(&mut self, addr: u16) -> u8 {
+        if let Some(s) = self.shadow.as_mut() {
+            s.read(addr as usize, addr);
+        }
+        self.zp[addr as usize]
+    }
+
+    pub(super) fn load_stack(&mut self, addr: u16) -> u8 {
+        let idx = (addr & 0xff) as usize;
+        if let Some(s) = self.shadow.as_mut() {
+            s.read(0x100 + idx, addr);
+        }
+        self.stack[idx]
+    }
+
+    pub(super) fn store_zp(&mut self, addr: u16, v: u8) {
+        if let Some(s) = self.shadow.as_mut() {
+            s.write(addr as usize);
+        }
+        self.zp[addr as usize] = v;
+    }
+
+    pub(super) fn store_stack(&mut self, addr: u16, v: u8) {
+        let idx = (addr & 0xff) as usize;
+        if let Some(s) = self.shadow.as_mut() {
+            s.write(0x100 + idx);
+        }
+        self.stack[idx] = v;
+    }
+
+    pub(crate) fn store_x(&mut self, addr: u16, v: u8) {
+        self.x[((addr - 0xFFFA) as usize)] = v; // offset into the 6-bye array
+    }
+
+    pub(crate) fn load_x(&mut self, addr: u16) -> u8 {
+        self.x[((addr - 0xFFFA) as usize)]
+    }
+
+    pub fn dump<T: AsRef<Path>>(&self, path: T) -> Result<(), Box<dyn Error>> {
+        let mut f = OpenOptions::new().write(true).create(true).open(path)?;
+        f.write_all(&self.zp)?;
+        f.write_all(&self.stack)?;
+        f.write_all(&self.x)?;
+        f.write_all(&self.special)?;
+        Ok(())
+    }
+}
+
+impl BusAccess for Mem {
+    fn load_u8(&mut self, addr: u16) -> u8 {
+        match addr {
+            a @ 0x0000..=0x00FF => self.load_zp(a),
+            0x0100..=0x01ff => self.load_stack(addr),
+            addr => panic!("Address {} not addressable", addr),
+        }
+    }
+
+    fn store_u8(&mut self, addr: u16, v: u8) {
+        match addr {
+            a @ 0x0000..=0x00ff => self.store_zp(a, v),
+            a @ 0x0100..=0x01ff => self.store_stack(a, v),
+            addr => panic!("Address {} not addressable", addr),
+        }
+    }
+
+    fn sync_pc(&mut self, pc: u16) {
+        if let Some(s) = self.shadow.as_mut() {
+            s.sync_pc(pc);
+        }
+    }
+}
+
+/// The DataBus
+/// data has to transfer between the accumulator and the internal registers of the microprocessor and outside sources by means of passing through
+///  the microprocessor to 8 lines called the data bus. The outside sources include (in our case) the program
+/// which controls the microprocessor, and the actual communications to the world through input/output ports.
+/// The duty of the data bus is to facilitate exchange of data between memory and the processor's internal registers.
+/// I/o operationS on this type of microprocessor are accomplished by reading and writing registers which
+/// actually represent connections to physical devices or to physical pins  which connect to physical devices.
+///
+/// This is the bus of the NES's 2A03. Its address space is decoded like this:
+///
+/// | range         | device                                                      |
+/// |---------------|-------------------------------------------------------------|
+/// | $0000-$07FF   | 2KB internal ram                                            |
+/// | $0800-$1FFF   | mirrors of the ram                                          |
+/// | $2000-$2007   | ppu registers                                               |
+/// | $2008-$3FFF   | mirrors of the ppu registers, every 8 bytes                 |
+/// | $4000-$4017   | apu and i/o registers (sound, oam dma, controllers)         |
+/// | $4018-$401F   | cpu test mode registers, disabled on retail consoles        |
+/// | $4020-$FFFF   | cartridge: expansion, PRG-RAM and PRG-ROM, as its mapper decodes it |
+///
+/// Nothing drives the data lines when an address decodes to nothing (an empty slot, the disabled test registers),
+/// so the cpu reads back whatever was last on the bus. We keep that last value as the open bus latch
+///
+/// A write to $4014 (OAMDMA, on the 2A03 itself) halts the cpu and copies the 256 bytes of page $XX00 to $2004, a
+/// read and a write per byte. With the cycle that halts the cpu, and one more to get onto a read cycle when it
+/// starts on the wrong one, that takes 513 or 514 cycles, which is most of what a game can spare in vblank.
+/// The DMC fetches its samples the same way, a byte at a time, 3 or 4 cycles each (2 when it cuts into an OAM DMA).
+/// The cpu is halted on a read, and when it carries on it does that read again: if the cycle the DMC asked on was the
+/// one reading a controller or $2007, the port sees two reads, and a bit or a byte goes missing. A request on any
+/// other cycle of the same instruction halts it on a read of something else, and does no harm
+#[derive(Default)]
+pub struct DataBus {
+    pub(crate) ram: Ram,
+    // the ppu, behind its eight ports
+    pub(crate) ppu: Option<Ppu>,
+    // the apu's registers at $4000-$4013, $4015 and $4017
+    pub(crate) apu: Option<Apu>,
+    // where the apu's mix (and the cartridge's sound chip) goes, once a cycle
+    pub(crate) audio: Option<AudioOut>,
+    // the devices in the two controller ports, at $4016 and $4017
+    pub(crate) ports: [Option<Box<dyn ControllerPort>>; 2],
+    // the rest of $4000-$4017, and all of it when there is no apu and nothing plugged in
+    pub(crate) io: Option<Box<dyn BusAccess>>,
+    pub(crate) cart: Option<Box<dyn Mapper>>,
+    // the last value that was on the data lines
+    open: u8,
+    // cpu cycles so far, for the parity of the next one
+    cycles: u64,
+    // the page a write to $4014 asked to copy to OAM
+    oam_dma: Option<u8>,
+    in_oam_dma: bool,
+    // cycles the DMC's fetches have taken from the cpu since it last asked
+    stolen: u64,
+    // the cycle of the current instruction the next access and the next tick fall on, and the reads so far with the
+    // cycle each was on
+    access: u32,
+    step: u32,
+    reads: Vec<(u32, u16)>,
+    // the region the cartridge asks for, and the one the host wants instead
+    cart_region: Region,
+    forced_region: Option<Region>,
+    // master clocks the ppu has still to catch up on
+    ppu_clock: u32,
+}
+
+impl DataBus {
+    pub fn new() -> Self {
+        Self {
+            ..Default::default()
+        }
+    }
+
+    /// a whole console: the ppu and apu attached and `cart` in the slot, with the controller ports left empty for the
+    /// host to fill. Fails if we do not have the cartridge's mapper
+    pub fn console(cart: Cartridge) -> Result<Self, CartridgeError> {
+        let mut bus = Self::new();
+        bus.attach_ppu(Ppu::new());
+        bus.attach_apu(Apu::new());
+        bus.insert(cart)?;
+        Ok(bus)
+    }
+
+    /// starts tracking reads of the 2KB of ram that happen before the byte is first written. See [Ram::enable_shadow]
+    pub fn enable_shadow(&mut self) {
+        self.ram.enable_shadow();
+    }
+
+    /// reads of never-written ram seen since the shadow was enabled. Empty if it never was
+    pub fn uninit_reads(&self) -> &[UninitRead] {
+        self.ram.uninit_reads()
+    }
+
+    pub fn take_uninit_reads(&mut self) -> Vec<UninitRead> {
+        self.ram.take_uninit_reads()
+    }
+
+    /// plugs a cartridge into the slot, replacing whatever was there. Fails if we do not have its mapper.
+    /// The console takes on the region the cartridge's header names, unless one has been forced
+    pub fn insert(&mut self, cart: Cartridge) -> Result<(), CartridgeError> {
+        let region = Region::from(cart.header.timing);
+        self.insert_board(mapper::from_cartridge(cart)?, region);
+        Ok(())
+    }
+
+    /// plugs in a board that did not come from a cartridge dump, like an NSF's, made for `region`
+    pub fn insert_board(&mut self, board: Box<dyn Mapper>, region: Region) {
+        self.cart = Some(board);
+        self.cart_region = region;
+        self.update_region();
+    }
+
+    /// cpu cycles since power on, DMA included
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// the region the console is running as
+    pub fn region(&self) -> Region {
+        self.forced_region.unwrap_or(self.cart_region)
+    }
+
+    /// runs as `region` whatever the cartridge says, or with `None` goes back to what it says. For headers that get
+    /// it wrong, and for playing NTSC games at Dendy speed
+    pub fn force_region(&mut self, region: Option<Region>) {
+        self.forced_region = region;
+        self.update_region();
+    }
+
+    // a DMC sample fetch, with the cpu halted
+    fn dmc_fetch(&mut self, addr: u16) {
+        // the read the cpu was halted on: the one on the cycle the request came
+        let cycle = self.step - 1;
+        let cpu_read = self.reads.iter().find(|&&(c, _)| c == cycle).map(|&(_, a)| a);
+        // the halt and a dummy cycle, and one more to get onto a read cycle. Inside an OAM DMA, which is already
+        // halted and reading on alternate cycles, only the realignment
+        let wait = if self.in_oam_dma {
+            1
+        } else {
+            2 + self.cycles % 2
+        };
+        for _ in 0..wait {
+            self.tick();
+        }
+        let v = self.read(addr);
+        self.tick();
+        if let Some(a) = self.apu.as_mut() {
+            a.dmc_fill(v);
+        }
+        if !self.in_oam_dma {
+            self.stolen += wait + 1;
+            // the halted read, again
+            if let Some(addr @ (0x2000..=0x3fff | 0x4016 | 0x4017)) = cpu_read {
+                self.read(addr);
+            }
+        }
+        // the halt does not move the instruction on
+        self.step = cycle + 1;
+    }
+
+    // a cpu read, as the bus answers it. [BusAccess::load_u8] also notes which cycle of the instruction it was on
+    fn read(&mut self, addr: u16) -> u8 {
+        let v = match addr {
+            0x0000..=0x1fff => Some(self.ram.load_u8(addr)),
+            0x2000..=0x3fff => {
+                let mut none = Unplugged;
+                let cart = slot(&mut self.cart, &mut none);
+                self.ppu.as_mut().map(|p| p.read_register(addr, cart))
+            }
+            // bit 5 of the apu's status is not driven
+            0x4015 if self.apu.is_some() => self.apu.as_mut().map(|a| a.read_status() | (self.open & 0x20)),
+            // the ports drive the low five bits, 0 when nothing is plugged in
+            0x4016 | 0x4017 if self.ports[addr as usize - 0x4016].is_some() || self.io.is_none() => {
+                let port = self.ports[addr as usize - 0x4016].as_mut();
+                Some(port.map_or(0, |p| p.read()) & 0x1f | (self.open & 0xe0))
+            }
+            0x4000..=0x4017 => self.io.as_mut().map(|d| d.load_u8(addr)),
+            0x4018..=0x401f => None,
+            0x4020..=0xffff => self.cart.as_mut().and_then(|c| c.cpu_load(addr)),
+        };
+        self.open = v.unwrap_or(self.open);
+        self.open
+    }
+
+    fn write(&mut self, addr: u16, v: u8) {
+        self.open = v;
+        if let Some(c) = self.cart.as_mut() {
+            c.cpu_bus(addr, v);
+        }
+        match addr {
+            0x0000..=0x1fff => self.ram.store_u8(addr, v),
+            0x2000..=0x3fff => {
+                let mut none = Unplugged;
+                let cart = slot(&mut self.cart, &mut none);
+                if let Some(p) = self.ppu.as_mut() {
+                    p.write_register(addr, v, cart)
+                }
+            }
+            0x4014 => self.oam_dma = Some(v),
+            0x4016 if self.ports.iter().any(Option::is_some) => {
+                for p in self.ports.iter_mut().flatten() {
+                    p.write(v & 0x07);
+                }
+            }
+            0x4000..=0x4013 | 0x4015 | 0x4017 if self.apu.is_some() => {
+                if let Some(a) = self.apu.as_mut() {
+                    a.write(addr, v)
+                }
+            }
+            0x4000..=0x4017 => {
+                if let Some(d) = self.io.as_mut() {
+                    d.store_u8(addr, v)
+                }
+            }
+            0x4018..=0x401f => (),
+            0x4020..=0xffff => {
+                if let Some(c) = self.cart.as_mut() {
+                    c.cpu_store(addr, v)
+                }
+            }
+        }
+    }
+
+    fn update_region(&mut self) {
+        let region = self.region();
+        if let Some(p) = self.ppu.as_mut() {
+            p.set_region(region);
+        }
+        if let Some(a) = self.apu.as_mut() {
+            a.set_region(region);
+        }
+        if let Some(a) = self.audio.as_mut() {
+            a.set_region(region);
+        }
+    }
+
+    pub fn eject(&mut self) -> Option<Box<dyn Mapper>> {
+        self.cart.take()
+    }
+
+    /// connects the ppu at $2000-$2007 (and its mirrors). From then on it runs three dots for every cpu cycle (3.2 on
+    /// PAL)
+    pub fn attach_ppu(&mut self, ppu: Ppu) {
+        self.ppu = Some(ppu);
+        self.update_region();
+    }
+
+    /// the ppu, for the host to take its frames from
+    pub fn ppu(&self) -> Option<&Ppu> {
+        self.ppu.as_ref()
+    }
+
+    /// connects the apu to its registers. It is clocked with the cpu, and shares the IRQ line with the cartridge
+    pub fn attach_apu(&mut self, apu: Apu) {
+        self.apu = Some(apu);
+        self.update_region();
+    }
+
+    /// sends the sound to `audio`: the apu's mix, with the cartridge's sound chip added, resampled to its rate
+    pub fn attach_audio(&mut self, audio: AudioOut) {
+        self.audio = Some(audio);
+        self.update_region();
+    }
+
+    /// stops sending the sound anywhere, handing back the output. [AudioOut::flush] it for the samples still pending
+    pub fn detach_audio(&mut self) -> Option<AudioOut> {
+        self.audio.take()
+    }
+
+    /// plugs `device` into controller port `port` (0 or 1), which reads at $4016 or $4017. Every write to $4016
+    /// reaches both ports
+    pub fn attach_controller(&mut self, port: usize, device: Box<dyn ControllerPort>) {
+        self.ports[port] = Some(device);
+    }
+
+    pub fn detach_controller(&mut self, port: usize) -> Option<Box<dyn ControllerPort>> {
+        self.ports[port].take()
+    }
+
+    /// connects the device that answers at $4000-$4017 where the apu and controllers do not
+    pub fn attach_io(&mut self, io: Box<dyn BusAccess>) {
+        self.io = Some(io);
+    }
+
+    // comeback
+    pub fn set(&mut self, v: u8) {
+        todo!()
+    }
+}
+
+// what is in the cartridge slot, as something the ppu can fetch from even when it is empty
+fn slot<'a>(cart: &'a mut Option<Box<dyn Mapper>>, none: &'a mut Unplugged) -> &'a mut dyn Mapper {
+    match cart.as_deref_mut() {
+        Some(c) => c,
+        None => none,
+    }
+}
+
+impl BusAccess for DataBus {
+    fn load_u8(&mut self, addr: u16) -> u8 {
+        self.reads.push((self.access, addr));
+        self.access += 1;
+        self.read(addr)
+    }
+
+    fn store_u8(&mut self, addr: u16, v: u8) {
+        self.access += 1;
+        self.write(addr, v)
+    }
+
+    fn sync_pc(&mut self, pc: u16) {
+        self.ram.sync_pc(pc);
+        self.access = 0;
+        self.step = 0;
+        self.reads.clear();
+    }
+
+    fn tick(&mut self) {
+        let mut none = Unplugged;
+        let cart = slot(&mut self.cart, &mut none);
+        if let Some(p) = self.ppu.as_mut() {
+            let region = p.region();
+            self.ppu_clock += region.cpu_divider();
+            while self.ppu_clock >= region.ppu_divider() {
+                self.ppu_clock -= region.ppu_divider();
+                p.tick(cart);
+            }
+        }
+        cart.cpu_tick();
+        if let Some(a) = self.apu.as_mut() {
+            a.tick();
+        }
+        if let Some(out) = self.audio.as_mut() {
+            let apu = self.apu.as_ref().map_or(0.0, |a| a.output());
+            out.push(apu + cart.audio());
+        }
+        self.cycles += 1;
+        self.step = self.step.saturating_add(1);
+        if let Some(addr) = self.apu.as_mut().and_then(|a| a.dmc_request()) {
+            self.dmc_fetch(addr);
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.cart.as_ref().is_some_and(|c| c.irq()) || self.apu.as_ref().is_some_and(|a| a.irq())
+    }
+
+    fn nmi(&mut self) -> bool {
+        self.ppu.as_mut().is_some_and(|p| p.take_nmi())
+    }
+
+    fn dma(&mut self) -> u64 {
+        let Some(page) = self.oam_dma.take() else {
+            return std::mem::take(&mut self.stolen);
+        };
+        let start = self.cycles;
+        self.in_oam_dma = true;
+        // the halt, then reads on even cycles and writes on odd ones
+        self.tick();
+        if self.cycles % 2 == 1 {
+            self.tick();
+        }
+        for lo in 0..=0xff {
+            let v = self.load_u8(u16::from_be_bytes([page, lo]));
+            self.tick();
+            self.store_u8(0x2004, v);
+            self.tick();
+        }
+        self.in_oam_dma = false;
+        self.cycles - start + std::mem::take(&mut self.stolen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::cartridge::Timing;
+    use crate::nes::controller::{buttons, Joypad};
+    use crate::nes::mapper::test_cart;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn nes_memory_map() {
+        let mut bus = DataBus::new();
+        // ram mirrors
+        bus.store_u8(0x0801, 0x5a);
+        assert_eq!(bus.load_u8(0x1801), 0x5a);
+        assert_eq!(bus.load_u8(0x0001), 0x5a);
+
+        // ppu ports every 8 bytes: $3FFE is PPUADDR, $2007 PPUDATA
+        bus.attach_ppu(Ppu::new());
+        bus.store_u8(0x3ffe, 0x3f);
+        bus.store_u8(0x3ffe, 0x01);
+        bus.store_u8(0x2007, 0x11);
+        bus.store_u8(0x2006, 0x3f);
+        bus.store_u8(0x2006, 0x01);
+        assert_eq!(bus.load_u8(0x2007), 0x11);
+
+        // nothing at the test registers: open bus
+        bus.store_u8(0x0000, 0x77);
+        assert_eq!(bus.load_u8(0x401a), 0x77);
+    }
+
+    #[test]
+    fn uninit_ram_reads() {
+        let mut bus = DataBus::new();
+        bus.enable_shadow();
+        bus.sync_pc(0xc000);
+        bus.store_u8(0x0010, 1);
+        // a mirror of a written byte is written too
+        bus.load_u8(0x0810);
+        bus.load_u8(0x0011);
+        assert_eq!(bus.take_uninit_reads(), [UninitRead { pc: 0xc000, addr: 0x0011 }]);
+    }
+
+    #[test]
+    fn oam_dma() {
+        let mut bus = DataBus::new();
+        bus.attach_ppu(Ppu::new());
+        for i in 0..0x100 {
+            bus.store_u8(0x0300 + i, i as u8 ^ 0x5a);
+        }
+        assert_eq!(bus.dma(), 0);
+        bus.store_u8(0x2003, 0x10);
+        bus.store_u8(0x4014, 0x03);
+        let first = bus.dma();
+        // OAM is written from OAMADDR on, wrapping round
+        bus.store_u8(0x2003, 0x10);
+        assert_eq!(bus.load_u8(0x2004), 0x5a);
+        bus.store_u8(0x2003, 0x0f);
+        assert_eq!(bus.load_u8(0x2004), 0xff ^ 0x5a);
+
+        // one cycle later, the other parity
+        bus.tick();
+        bus.store_u8(0x4014, 0x03);
+        let mut lengths = [first, bus.dma()];
+        lengths.sort();
+        assert_eq!(lengths, [513, 514]);
+    }
+
+    // a 4 cycle absolute read of `addr`, LDA $xxxx, ticking after each access as the cpu does. `then` runs once the
+    // access of cycle `cycle` is done (the read is cycle 3), before that cycle's tick
+    fn absolute_read(bus: &mut DataBus, addr: u16, cycle: u32, then: impl FnOnce(&mut DataBus)) -> u8 {
+        bus.sync_pc(0x0000);
+        let mut then = Some(then);
+        let mut v = 0;
+        for (c, a) in [0, 1, 2, addr].into_iter().enumerate() {
+            v = bus.load_u8(a);
+            if c as u32 == cycle {
+                then.take().unwrap()(bus);
+            }
+            bus.tick();
+        }
+        v
+    }
+
+    // a one byte sample, which the DMC asks for on the next tick
+    fn start_sample(bus: &mut DataBus) {
+        bus.write(0x4013, 0);
+        bus.write(0x4015, 0x10);
+    }
+
+    #[test]
+    fn dmc_dma_steals_cycles_and_repeats_reads() {
+        // 1, 2, 3 in vram, with $2007's read buffer primed
+        let vram = || {
+            let mut bus = DataBus::new();
+            bus.attach_ppu(Ppu::new());
+            bus.attach_apu(Apu::new());
+            bus.store_u8(0x2006, 0x20);
+            bus.store_u8(0x2006, 0x00);
+            for v in 1..=3 {
+                bus.store_u8(0x2007, v);
+            }
+            bus.store_u8(0x2006, 0x20);
+            bus.store_u8(0x2006, 0x00);
+            bus.load_u8(0x2007);
+            bus
+        };
+
+        // the fetch lands on the cycle reading $2007
+        let mut bus = vram();
+        assert_eq!(absolute_read(&mut bus, 0x2007, 3, start_sample), 1);
+        assert!((3..=4).contains(&bus.dma()));
+        assert_eq!(bus.dma(), 0);
+        // the repeated read took the 2
+        assert_eq!(absolute_read(&mut bus, 0x2007, 0, |_| ()), 3);
+
+        // the fetch lands on the operand fetch of the same instruction: nothing read twice
+        let mut bus = vram();
+        assert_eq!(absolute_read(&mut bus, 0x2007, 1, start_sample), 1);
+        assert!((3..=4).contains(&bus.dma()));
+        assert_eq!(absolute_read(&mut bus, 0x2007, 0, |_| ()), 2);
+    }
+
+    #[test]
+    fn controllers() {
+        let pad = Rc::new(RefCell::new(Joypad::new()));
+        let mut bus = DataBus::new();
+        bus.attach_apu(Apu::new());
+        bus.attach_controller(0, Box::new(pad.clone()));
+        pad.borrow_mut().set_buttons(buttons::A | buttons::SELECT);
+        bus.store_u8(0x4016, 1);
+        bus.store_u8(0x4016, 0);
+        // the top three bits are whatever was last on the bus
+        bus.store_u8(0x0000, 0xe0);
+        bus.load_u8(0x0000);
+        let bits: Vec<u8> = (0..3).map(|_| bus.load_u8(0x4016)).collect();
+        assert_eq!(bits, [0xe1, 0xe0, 0xe1]);
+        // nothing in port 2
+        assert_eq!(bus.load_u8(0x4017), 0xe0);
+
+        // a DMC fetch landing on a read reads again, and the controller shifts out a button for nothing
+        bus.store_u8(0x4016, 1);
+        bus.store_u8(0x4016, 0);
+        assert_eq!(absolute_read(&mut bus, 0x4016, 3, start_sample) & 0x1f, 1);
+        assert_eq!(absolute_read(&mut bus, 0x4016, 0, |_| ()) & 0x1f, 1);
+    }
+
+    #[test]
+    fn regions() {
+        // a PAL cartridge
+        let mut cart = test_cart(0, 0, 0x4000, 0x4000, 0x2000, 0x2000);
+        cart.header.timing = Timing::Pal;
+        let mut bus = DataBus::new();
+        bus.attach_ppu(Ppu::new());
+        bus.insert(cart).unwrap();
+        assert_eq!(bus.region(), Region::Pal);
+        // 3.2 dots a cycle
+        for _ in 0..5 {
+            bus.tick();
+        }
+        assert_eq!(bus.ppu.as_ref().unwrap().dot(), 16);
+
+        bus.force_region(Some(Region::Dendy));
+        assert_eq!(bus.ppu.as_ref().unwrap().region(), Region::Dendy);
+        bus.force_region(None);
+        assert_eq!(bus.region(), Region::Pal);
+    }
+
+    #[test]
+    fn vblank_read_race_through_the_cpu() {
+        use crate::six502::six502::Six502;
+        use crate::Cpu;
+
+        // LDA $2002, STA $00, LDA $2002, STA $01 at $8000
+        let mut cart = test_cart(0, 0, 0x8000, 0x8000, 0x2000, 0x2000);
+        let prg = [0xad, 0x02, 0x20, 0x85, 0x00, 0xad, 0x02, 0x20, 0x85, 0x01];
+        cart.prg_rom[..prg.len()].copy_from_slice(&prg);
+        cart.prg_rom[0x7ffc..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80]);
+        // the two reads, with the first instruction starting on (`scanline`, `dot`)
+        let run = |scanline, dot| {
+            let mut bus = DataBus::new();
+            bus.attach_ppu(Ppu::new());
+            bus.insert(cart.clone()).unwrap();
+            let mut cpu = Six502::with_bus(bus);
+            cpu.start().unwrap();
+            while cpu.bus().ppu.as_ref().map(|p| (p.scanline(), p.dot())) != Some((scanline, dot)) {
+                cpu.bus_mut().tick();
+            }
+            for _ in 0..4 {
+                cpu.exec().unwrap();
+            }
+            let bus = cpu.bus_mut();
+            (bus.load_u8(0x0000) & 0x80, bus.load_u8(0x0001) & 0x80)
+        };
+        // the read is the fourth cycle, nine dots in. A cycle before vblank it sees the flag clear, and the next read
+        // sees it set
+        assert_eq!(run(240, 330), (0, 0x80));
+        // a dot before the flag goes up, which stops it from going up at all
+        assert_eq!(run(240, 333), (0, 0));
+    }
+
+    #[test]
+    fn audio() {
+        let samples = Rc::new(RefCell::new(vec![]));
+        let out = samples.clone();
+        let mut bus = DataBus::new();
+        bus.attach_apu(Apu::new());
+        bus.attach_audio(AudioOut::new(
+            44100,
+            Region::Ntsc,
+            Box::new(move |s: &[f32]| out.borrow_mut().extend_from_slice(s)),
+        ));
+        // pulse 1 at full constant volume, about 440Hz
+        bus.store_u8(0x4015, 0x01);
+        bus.store_u8(0x4000, 0xbf);
+        bus.store_u8(0x4002, 0xfd);
+        bus.store_u8(0x4003, 0x00);
+        for _ in 0..29781 {
+            bus.tick();
+        }
+        bus.detach_audio().unwrap().flush();
+        // a frame's worth, and not silence
+        let samples = samples.borrow();
+        assert!((730..=740).contains(&samples.len()), "{}", samples.len());
+        assert!(samples.iter().any(|s| s.abs() > 0.05));
+        assert!(bus.detach_audio().is_none());
+    }
+}
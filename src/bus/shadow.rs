@@ -0,0 +1,107 @@
+//! Shadow memory for catching reads of RAM that was never written.
+//! Real RAM chips power up holding whatever charge their cells settled on, so a program that reads a location before
+//! storing to it works by accident in an emulator (where everything starts zeroed) and misbehaves on hardware.
+//! The shadow keeps one bit per byte of the RAM it watches; a store sets the bit, a load of a location whose bit is
+//! still clear gets recorded, along with the address of the instruction that did it. It is the 6502 take on valgrind's
+//! uninitialised-read check.
+//!
+//! [Ram](crate::Ram), and the [DataBus](crate::DataBus) through it, shadow all 2KB, a bit per physical byte, so the
+//! mirrors share it. [Mem](crate::Mem) shadows its zero page and stack, which is all of it the bus decodes: the rest
+//! holds the program, loaded from a file and so written by definition.
+use std::fmt;
+
+/// A load from a RAM location that had not been stored to since power on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UninitRead {
+    /// address of the opcode of the instruction that made the load
+    pub pc: u16,
+    /// the address the cpu put on the address bus, before any mirroring
+    pub addr: u16,
+}
+
+impl fmt::Display for UninitRead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "read of uninitialized ram at ${:04X} by instruction at ${:04X}",
+            self.addr, self.pc
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Shadow {
+    // one bit per byte of the watched memory. set once the byte has been written
+    written: Vec<u64>,
+    // one bit per byte. set once an uninitialised read of the byte has been reported, so a loop polling the same
+    // location does not flood the report
+    reported: Vec<u64>,
+    // the pc of the instruction currently executing, as last told by the cpu
+    pc: u16,
+    reads: Vec<UninitRead>,
+}
+
+impl Shadow {
+    /// `size` is the number of bytes in the memory being shadowed, not the size of the address space it is mapped to
+    pub(crate) fn new(size: usize) -> Self {
+        let words = size.div_ceil(64);
+        Self {
+            written: vec![0; words],
+            reported: vec![0; words],
+            pc: 0,
+            reads: Vec::new(),
+        }
+    }
+
+    pub(crate) fn sync_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// records a store to byte `idx` of the watched memory
+    pub(crate) fn write(&mut self, idx: usize) {
+        self.written[idx / 64] |= 1 << (idx % 64);
+    }
+
+    /// checks a load of byte `idx` of the watched memory, which the cpu addressed as `addr`
+    pub(crate) fn read(&mut self, idx: usize, addr: u16) {
+        let (word, bit) = (idx / 64, 1u64 << (idx % 64));
+        if self.written[word] & bit != 0 || self.reported[word] & bit != 0 {
+            return;
+        }
+        self.reported[word] |= bit;
+        self.reads.push(UninitRead { pc: self.pc, addr });
+    }
+
+    pub(crate) fn reads(&self) -> &[UninitRead] {
+        &self.reads
+    }
+
+    pub(crate) fn take_reads(&mut self) -> Vec<UninitRead> {
+        std::mem::take(&mut self.reads)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_first_uninit_read_only() {
+        let mut s = Shadow::new(0x800);
+        s.sync_pc(0xc000);
+        s.write(0x10);
+        s.read(0x10, 0x0010);
+        s.read(0x11, 0x0811);
+        s.sync_pc(0xc002);
+        s.read(0x11, 0x0011);
+        assert_eq!(
+            s.reads(),
+            &[UninitRead {
+                pc: 0xc000,
+                addr: 0x0811
+            }]
+        );
+        assert_eq!(s.take_reads().len(), 1);
+        assert!(s.reads().is_empty());
+    }
+}
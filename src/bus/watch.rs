@@ -0,0 +1,253 @@
+//! Watchpoints on the bus.
+//! [Watched] sits between the cpu and any [BusAccess] implementor and looks at every access going through it, so a
+//! watchpoint works the same on a ram location as on a device register (a PPU port, a VIA timer, an ACIA status byte).
+//! When an access matches, the details are latched and the cpu, which asks the bus for a break at the end of every
+//! instruction, stops and hands them back to the host as the error of [crate::Cpu::exec].
+use super::BusAccess;
+use bitflags::bitflags;
+use std::{error::Error, fmt, ops::RangeInclusive};
+
+bitflags! {
+    /// the kinds of access a [Watchpoint] triggers on
+    pub struct On: u8 {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        /// opcode fetches. Operand bytes are reads
+        const EXEC = 1 << 2;
+    }
+}
+
+/// The kind of a single bus access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Exec,
+}
+
+impl Access {
+    fn on(self) -> On {
+        match self {
+            Access::Read => On::READ,
+            Access::Write => On::WRITE,
+            Access::Exec => On::EXEC,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Watchpoint {
+    range: RangeInclusive<u16>,
+    on: On,
+    // only trigger when this value is read or written
+    value: Option<u8>,
+}
+
+impl Watchpoint {
+    /// watch a single address
+    pub fn at(addr: u16, on: On) -> Self {
+        Self::range(addr..=addr, on)
+    }
+
+    /// watch every address in `range`
+    pub fn range(range: RangeInclusive<u16>, on: On) -> Self {
+        Self {
+            range,
+            on,
+            value: None,
+        }
+    }
+
+    /// only trigger when the byte transferred is `v`
+    pub fn value(mut self, v: u8) -> Self {
+        self.value = Some(v);
+        self
+    }
+
+    fn matches(&self, access: Access, addr: u16, v: u8) -> bool {
+        self.on.contains(access.on())
+            && self.range.contains(&addr)
+            && self.value.is_none_or(|want| want == v)
+    }
+}
+
+/// What triggered a watchpoint. Returned to the host as the error of `exec`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    /// index of the watchpoint, as returned by [Watched::add]
+    pub id: usize,
+    pub access: Access,
+    pub addr: u16,
+    /// the byte read, written or fetched
+    pub value: u8,
+    /// address of the opcode of the instruction that made the access
+    pub pc: u16,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.access {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::Exec => "exec",
+        };
+        write!(
+            f,
+            "watchpoint {} hit: {} of ${:02X} at ${:04X} by instruction at ${:04X}",
+            self.id, kind, self.value, self.addr, self.pc
+        )
+    }
+}
+
+impl Error for WatchHit {}
+
+/// A bus that checks every access against a set of watchpoints before passing it on to `inner`
+#[derive(Debug, Default)]
+pub struct Watched<B: BusAccess> {
+    inner: B,
+    // removed watchpoints leave a hole so ids stay stable
+    points: Vec<Option<Watchpoint>>,
+    // the first hit since the cpu last asked. later ones within the same instruction are dropped
+    hit: Option<WatchHit>,
+    pc: u16,
+}
+
+impl<B: BusAccess> Watched<B> {
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            points: Vec::new(),
+            hit: None,
+            pc: 0,
+        }
+    }
+
+    /// adds a watchpoint and returns its id
+    pub fn add(&mut self, w: Watchpoint) -> usize {
+        self.points.push(Some(w));
+        self.points.len() - 1
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Watchpoint> {
+        self.points.get_mut(id).and_then(Option::take)
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut B {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    fn check(&mut self, access: Access, addr: u16, value: u8) {
+        if self.hit.is_some() {
+            return;
+        }
+        let id = self
+            .points
+            .iter()
+            .position(|w| w.as_ref().is_some_and(|w| w.matches(access, addr, value)));
+        if let Some(id) = id {
+            self.hit = Some(WatchHit {
+                id,
+                access,
+                addr,
+                value,
+                pc: self.pc,
+            });
+        }
+    }
+}
+
+impl<B: BusAccess> BusAccess for Watched<B> {
+    fn load_u8(&mut self, addr: u16) -> u8 {
+        let v = self.inner.load_u8(addr);
+        self.check(Access::Read, addr, v);
+        v
+    }
+
+    fn store_u8(&mut self, addr: u16, v: u8) {
+        self.check(Access::Write, addr, v);
+        self.inner.store_u8(addr, v);
+    }
+
+    fn fetch_u8(&mut self, addr: u16) -> u8 {
+        let v = self.inner.fetch_u8(addr);
+        self.check(Access::Exec, addr, v);
+        v
+    }
+
+    fn sync_pc(&mut self, pc: u16) {
+        self.pc = pc;
+        self.inner.sync_pc(pc);
+    }
+
+    fn take_break(&mut self) -> Option<WatchHit> {
+        self.hit.take().or_else(|| self.inner.take_break())
+    }
+
+    fn tick(&mut self) {
+        self.inner.tick();
+    }
+
+    fn irq(&self) -> bool {
+        self.inner.irq()
+    }
+
+    fn nmi(&mut self) -> bool {
+        self.inner.nmi()
+    }
+
+    fn dma(&mut self) -> u64 {
+        self.inner.dma()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::six502::ram::Ram;
+
+    #[test]
+    fn range_and_value_filters() {
+        let mut bus = Watched::new(Ram::new());
+        let id = bus.add(Watchpoint::range(0x0200..=0x02ff, On::WRITE).value(0x42));
+        bus.sync_pc(0xc123);
+        bus.store_u8(0x0200, 0x41);
+        bus.load_u8(0x0210);
+        bus.store_u8(0x0300, 0x42);
+        assert_eq!(bus.take_break(), None);
+
+        bus.store_u8(0x02ff, 0x42);
+        assert_eq!(
+            bus.take_break(),
+            Some(WatchHit {
+                id,
+                access: Access::Write,
+                addr: 0x02ff,
+                value: 0x42,
+                pc: 0xc123
+            })
+        );
+        assert_eq!(bus.take_break(), None);
+    }
+
+    #[test]
+    fn exec_is_not_read() {
+        let mut bus = Watched::new(Ram::new());
+        bus.add(Watchpoint::at(0x0400, On::EXEC));
+        bus.load_u8(0x0400);
+        assert_eq!(bus.take_break(), None);
+        bus.fetch_u8(0x0400);
+        assert_eq!(bus.take_break().map(|h| h.access), Some(Access::Exec));
+    }
+}
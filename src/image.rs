@@ -0,0 +1,95 @@
+//! Writers for 24-bit RGB images, with no dependencies.
+//! PPM is the simplest format there is; PNG is for everything that does not read PPM. The PNG writer uses deflate's
+//! stored (uncompressed) blocks, so files are about the size of the raw pixels, but any viewer will open them.
+use std::io::{self, Write};
+
+/// writes `rgb` (three bytes per pixel, rows top to bottom) as a binary PPM (P6)
+pub fn write_ppm<W: Write>(mut w: W, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+    assert_eq!(rgb.len(), width * height * 3);
+    write!(w, "P6\n{} {}\n255\n", width, height)?;
+    w.write_all(rgb)
+}
+
+/// writes `rgb` (three bytes per pixel, rows top to bottom) as a PNG
+pub fn write_png<W: Write>(mut w: W, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+    assert_eq!(rgb.len(), width * height * 3);
+    w.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    // bit depth 8, colour type 2 (truecolour), deflate, adaptive filtering, no interlace
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+    chunk(&mut w, b"IHDR", &ihdr)?;
+
+    // every scanline starts with its filter type. 0 is no filtering
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for row in rgb.chunks(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    chunk(&mut w, b"IDAT", &zlib_stored(&raw))?;
+    chunk(&mut w, b"IEND", &[])
+}
+
+fn chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    let crc = crc32(crc32(0xffff_ffff, kind), data) ^ 0xffff_ffff;
+    w.write_all(&crc.to_be_bytes())
+}
+
+// a zlib stream made of stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // cmf/flg: deflate with a 32K window, no dictionary, header checksum making it a multiple of 31
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &d in data {
+        a = (a + d as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        // reference values from zlib
+        assert_eq!(crc32(0xffff_ffff, b"123456789") ^ 0xffff_ffff, 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+}
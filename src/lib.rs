@@ -1,13 +1,34 @@
 #![allow(unused_imports, dead_code)]
 mod bus;
+mod image;
 mod macros;
+mod nes;
 mod six502;
+mod wav;
 
+pub use bus::heat::{Counts, HeatView, Profiled};
+pub use bus::shadow::UninitRead;
+pub use bus::watch::{Access, On, WatchHit, Watched, Watchpoint};
+pub use bus::{BusAccess, DataBus, Mem};
+pub use nes::apu::Apu;
+pub use nes::audio::{AudioOut, AudioSink, WavSink};
+pub use nes::cartridge::{Cartridge, CartridgeError};
+pub use nes::controller::{buttons, ControllerPort, Joypad};
+pub use nes::nsf::{chips, render_wav, Nsf, NsfError, NsfPlayer};
+pub use nes::ntsc::{NtscFilter, NtscSettings};
+pub use nes::palette::{Palette, PaletteError};
+pub use nes::ppu::Ppu;
+pub use nes::region::Region;
+pub use nes::video::{render_console_png, render_png, Video};
 pub use six502::addressing::AddressingMode;
+pub use six502::ram::Ram;
+pub use six502::six502::Six502;
 
 use six502::Op;
 pub trait Cpu: ByteAccess {
-    fn new() -> Self;
+    fn new() -> Self
+    where
+        Self: Default;
 
     fn load_u8_bump_pc(&mut self) -> u8;
 
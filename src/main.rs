@@ -1,8 +1,108 @@
 use cursive::views::TextView;
+use nes::{render_png, render_wav, Cartridge, Nsf, NtscFilter, NtscSettings, Palette, Region, Video};
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::process;
+
+// frames to run before the one that is written: a second, past the blank screens most games start on
+const FRAMES: u64 = 60;
+// samples a second in the WAV files `--nsf` writes
+const RATE: u32 = 44100;
+
+const USAGE: &str = "usage: nes [--video palette|ntsc [--region ntsc|pal|dendy] [--palette <file.pal>]
+    [--sharpness <-1..1>] [--saturation <-1..1>] [--no-crawl] <rom> <out.png>
+    | --nsf <file> <song> <seconds> <out.wav>]";
 
 fn main() {
-    let mut siv = cursive::default();
-    siv.add_global_callback('q', |c| c.quit());
-    siv.add_layer(TextView::new("Welcome to 6502. press <q> to exit"));
-    siv.run();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.as_slice() {
+        [flag, video, rest @ ..] if flag == "--video" && rest.len() >= 2 => render(video, rest),
+        [flag, file, song, seconds, out] if flag == "--nsf" => play(file, song, seconds, out),
+        [] => {
+            let mut siv = cursive::default();
+            siv.add_global_callback('q', |c| c.quit());
+            siv.add_layer(TextView::new("Welcome to 6502. press <q> to exit"));
+            siv.run();
+            return;
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+// a frame of a rom, through the `video` output, into a PNG. `args` are the options, then the rom and the PNG
+fn render(video: &str, mut args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut video: Video = video.parse()?;
+    let mut region = None;
+    // the NTSC filter's knobs, if any were turned
+    let mut ntsc: Option<NtscSettings> = None;
+    loop {
+        match args {
+            [flag, v, rest @ ..] if flag == "--region" => {
+                region = Some(v.parse::<Region>()?);
+                args = rest;
+            }
+            [flag, file, rest @ ..] if flag == "--palette" => {
+                // the NTSC filter makes its colours from the signal, and has no use for one
+                let Video::Palette(p) = &mut video else {
+                    return Err("--palette goes with --video palette".into());
+                };
+                *p = Palette::open(file)?;
+                args = rest;
+            }
+            [flag, v, rest @ ..] if flag == "--sharpness" => {
+                ntsc.get_or_insert_with(Default::default).sharpness = knob(flag, v)?;
+                args = rest;
+            }
+            [flag, v, rest @ ..] if flag == "--saturation" => {
+                ntsc.get_or_insert_with(Default::default).saturation = knob(flag, v)?;
+                args = rest;
+            }
+            [flag, rest @ ..] if flag == "--no-crawl" => {
+                ntsc.get_or_insert_with(Default::default).dot_crawl = false;
+                args = rest;
+            }
+            [rom, out] => {
+                if let Some(settings) = ntsc {
+                    let Video::Ntsc(f) = &mut video else {
+                        return Err("--sharpness, --saturation and --no-crawl go with --video ntsc".into());
+                    };
+                    *f = NtscFilter::new(settings);
+                }
+                let cart = Cartridge::open(rom)?;
+                return render_png(cart, &video, region, FRAMES, BufWriter::new(File::create(out)?));
+            }
+            _ => return Err(USAGE.into()),
+        }
+    }
+}
+
+// `seconds` of song `song` (from 1, as players number them) of the NSF `file`, into the WAV file `out`
+fn play(file: &str, song: &str, seconds: &str, out: &str) -> Result<(), Box<dyn Error>> {
+    let nsf = Nsf::open(file)?;
+    let song = match song.parse::<u8>() {
+        Ok(n) if (1..=nsf.songs).contains(&n) => n - 1,
+        _ => return Err(format!("{:?} is not a song of the {} in {}", song, nsf.songs, file).into()),
+    };
+    let seconds = match seconds.parse::<f64>() {
+        Ok(s) if s > 0.0 => s,
+        _ => return Err(format!("{:?} is not a number of seconds", seconds).into()),
+    };
+    render_wav(nsf, song, seconds, RATE, BufWriter::new(File::create(out)?))?;
+    Ok(())
+}
+
+// an NTSC filter setting, which goes from -1 to 1
+fn knob(flag: &str, v: &str) -> Result<f32, String> {
+    match v.parse::<f32>() {
+        Ok(k) if (-1.0..=1.0).contains(&k) => Ok(k),
+        _ => Err(format!("{} takes a number from -1 to 1, not {:?}", flag, v)),
+    }
 }
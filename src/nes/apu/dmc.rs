@@ -0,0 +1,180 @@
+//! The delta modulation channel. It plays 1 bit delta encoded samples from PRG: each bit moves a 7 bit output level
+//! up or down by 2. $4011 sets the level directly, which is also how games play raw PCM (drums, voices), a byte at a
+//! time from the cpu.
+//! Samples are read from memory by the channel itself, which has no bus of its own: when its one byte buffer is
+//! empty it asks for a DMA, and the bus halts the cpu for a few cycles to fetch the byte (see
+//! [DataBus](crate::bus::DataBus)). When the last byte of a sample has been fetched it loops, or raises its IRQ.
+//! [reference](https://www.nesdev.org/wiki/APU_DMC)
+
+#[derive(Debug)]
+pub(crate) struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    period: u16,
+    timer: u16,
+    pub(super) irq: bool,
+
+    level: u8,
+    sample_addr: u16,
+    sample_len: u16,
+    addr: u16,
+    pub(super) remaining: u16,
+    buffer: Option<u8>,
+    // a fetch has been asked for and not yet delivered
+    fetching: bool,
+
+    shift: u8,
+    bits: u8,
+    silence: bool,
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Self {
+            irq_enabled: false,
+            looping: false,
+            period: 428,
+            timer: 0,
+            irq: false,
+            level: 0,
+            sample_addr: 0xc000,
+            sample_len: 1,
+            addr: 0xc000,
+            remaining: 0,
+            buffer: None,
+            fetching: false,
+            shift: 0,
+            bits: 8,
+            silence: true,
+        }
+    }
+}
+
+impl Dmc {
+    /// `periods` is the region's timer period table
+    pub(super) fn write(&mut self, reg: u16, v: u8, periods: &[u16; 16]) {
+        match reg {
+            0 => {
+                self.irq_enabled = v & 0x80 != 0;
+                self.looping = v & 0x40 != 0;
+                self.period = periods[v as usize & 0x0f];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            1 => self.level = v & 0x7f,
+            2 => self.sample_addr = 0xc000 | (v as u16) << 6,
+            _ => self.sample_len = (v as u16) << 4 | 1,
+        }
+    }
+
+    // bit 4 of $4015
+    pub(super) fn set_enabled(&mut self, on: bool) {
+        self.irq = false;
+        if !on {
+            self.remaining = 0;
+        } else if self.remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.addr = self.sample_addr;
+        self.remaining = self.sample_len;
+    }
+
+    /// the address of the next sample byte, if the channel wants it fetched
+    pub(super) fn request(&mut self) -> Option<u16> {
+        if self.buffer.is_some() || self.remaining == 0 || self.fetching {
+            return None;
+        }
+        self.fetching = true;
+        Some(self.addr)
+    }
+
+    /// the byte a DMA fetched
+    pub(super) fn fill(&mut self, v: u8) {
+        self.fetching = false;
+        self.buffer = Some(v);
+        // the address wraps round to $8000
+        self.addr = self.addr.checked_add(1).unwrap_or(0x8000);
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // every cpu cycle; the periods are in cpu cycles
+    pub(super) fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits -= 1;
+        if self.bits == 0 {
+            self.bits = 8;
+            match self.buffer.take() {
+                Some(b) => {
+                    self.silence = false;
+                    self.shift = b;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// 0-127
+    pub(super) fn output(&self) -> u8 {
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plays_and_loops() {
+        let periods = [1; 16];
+        let mut d = Dmc::default();
+        d.write(0, 0x80, &periods);
+        d.write(1, 64, &periods);
+        d.write(3, 0, &periods);
+        d.set_enabled(true);
+        // one byte of all 1s: up by 2 on each of its 8 bits
+        assert_eq!(d.request(), Some(0xc000));
+        assert_eq!(d.request(), None);
+        d.fill(0xff);
+        assert!(d.irq);
+        for _ in 0..8 {
+            d.clock_timer();
+        }
+        assert_eq!(d.output(), 64);
+        for _ in 0..8 {
+            d.clock_timer();
+        }
+        assert_eq!(d.output(), 80);
+
+        // looping starts over instead
+        d.write(0, 0x40, &periods);
+        d.set_enabled(true);
+        assert_eq!(d.request(), Some(0xc000));
+        d.fill(0);
+        assert_eq!((d.remaining, d.irq), (1, false));
+    }
+}
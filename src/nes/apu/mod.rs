@@ -0,0 +1,420 @@
+//! The 2A03's audio processing unit.
+//! Five channels (two pulses, a triangle, noise and the DMC, a 1 bit delta sample player) built out of a handful of
+//! shared parts: timers that divide the cpu clock down to a pitch, sequencers that step through a waveform, length
+//! counters that silence a note after a set time, and envelopes that fade it. The frame counter ties them together,
+//! clocking the envelopes (and the triangle's linear counter) four times a frame, the length counters and sweeps
+//! twice, and in its 4 step mode raising an IRQ once a frame.
+//!
+//! | register      |                                                                                    |
+//! |---------------|------------------------------------------------------------------------------------|
+//! | $4000-$4003   | pulse 1: `DDLCVVVV` duty, halt, constant volume, volume; `EPPPNSSS` sweep; period    |
+//! | $4004-$4007   | pulse 2                                                                            |
+//! | $4008-$400B   | triangle: `CRRRRRRR` control, linear counter reload; period                         |
+//! | $400C-$400F   | noise: `..LCVVVV` halt, constant volume, volume; `M...PPPP` mode, period; length      |
+//! | $4010-$4013   | DMC: `IL..RRRR` IRQ enable, loop, rate; `.DDDDDDD` level; sample address; sample length |
+//! | $4015         | write `...DNT21` channel enables. Read `IF.DNT21` DMC and frame IRQs, lengths above 0 |
+//! | $4017         | `MI......` 5 step mode, IRQ inhibit                                                 |
+//!
+//! The last register of each channel also loads its length counter, from [LENGTHS].
+//!
+//! The channels are not simply added: the pulses share one DAC and the other three another, and both are resistor
+//! ladders whose output flattens as it rises, so loud channels squash each other a little. [Apu::output] uses the
+//! usual fit of the two curves.
+//! [reference](https://www.nesdev.org/wiki/APU)
+use super::region::Region;
+
+mod dmc;
+mod noise;
+mod pulse;
+mod triangle;
+
+use dmc::Dmc;
+use noise::Noise;
+use pulse::Pulse;
+use triangle::Triangle;
+
+/// the length counter load values, indexed by bits 3-7 of a channel's last register
+pub(crate) const LENGTHS: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16,
+    28, 32, 30,
+];
+
+/// Silences a channel after a set time, unless halted
+#[derive(Debug, Default)]
+pub(super) struct Length {
+    enabled: bool,
+    pub(super) halt: bool,
+    count: u8,
+}
+
+impl Length {
+    pub(super) fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.count = LENGTHS[index as usize & 0x1f];
+        }
+    }
+
+    pub(super) fn set_enabled(&mut self, on: bool) {
+        self.enabled = on;
+        if !on {
+            self.count = 0;
+        }
+    }
+
+    pub(super) fn active(&self) -> bool {
+        self.count > 0
+    }
+
+    // on half frames
+    pub(super) fn clock(&mut self) {
+        if !self.halt && self.count > 0 {
+            self.count -= 1;
+        }
+    }
+}
+
+/// A volume that is either constant or fades from 15 to 0, looping if asked to
+#[derive(Debug, Default)]
+pub(super) struct Envelope {
+    pub(super) constant: bool,
+    pub(super) looping: bool,
+    // the constant volume, or the fade's period
+    pub(super) volume: u8,
+    pub(super) start: bool,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    // $4000, $4004 and $400C: `..LCVVVV`
+    pub(super) fn write(&mut self, v: u8) {
+        self.looping = v & 0x20 != 0;
+        self.constant = v & 0x10 != 0;
+        self.volume = v & 0x0f;
+    }
+
+    // on quarter frames
+    pub(super) fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub(super) fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+pub struct Apu {
+    region: Region,
+    pulses: [Pulse; 2],
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    // cpu cycles since the frame counter's sequence started
+    frame_cycle: u32,
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    // a $4017 write waiting to restart the sequence, and in how many cycles
+    frame_reset: Option<u8>,
+    // every cpu cycle, for telling the two halves of an apu cycle apart
+    cycles: u64,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            region: Region::Ntsc,
+            pulses: [Pulse::new(true), Pulse::new(false)],
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::default(),
+            frame_cycle: 0,
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_reset: None,
+            cycles: 0,
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// the frame counter's timing and the noise and DMC periods depend on the region
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// a cpu write to $4000-$4013, $4015 or $4017
+    pub fn write(&mut self, addr: u16, v: u8) {
+        match addr {
+            0x4000..=0x4007 => self.pulses[(addr as usize >> 2) & 1].write(addr & 0x03, v),
+            0x4008..=0x400b => self.triangle.write(addr & 0x03, v),
+            0x400c..=0x400f => self.noise.write(addr & 0x03, v, self.region.noise_periods()),
+            0x4010..=0x4013 => self.dmc.write(addr & 0x03, v, self.region.dmc_periods()),
+            0x4015 => {
+                for (i, p) in self.pulses.iter_mut().enumerate() {
+                    p.length.set_enabled(v & (1 << i) != 0);
+                }
+                self.triangle.length.set_enabled(v & 0x04 != 0);
+                self.noise.length.set_enabled(v & 0x08 != 0);
+                self.dmc.set_enabled(v & 0x10 != 0);
+            }
+            0x4017 => {
+                self.five_step = v & 0x80 != 0;
+                self.irq_inhibit = v & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                // the sequence restarts 3 cycles on when written during an apu cycle, 4 when between two
+                self.frame_reset = Some(if self.cycles.is_multiple_of(2) { 3 } else { 4 });
+            }
+            _ => (),
+        }
+    }
+
+    /// a cpu read of $4015. Bit 5 is not driven; the caller fills it in from the open bus
+    pub fn read_status(&mut self) -> u8 {
+        let mut v = 0;
+        for (i, p) in self.pulses.iter().enumerate() {
+            v |= (p.length.active() as u8) << i;
+        }
+        v |= (self.triangle.length.active() as u8) << 2;
+        v |= (self.noise.length.active() as u8) << 3;
+        v |= ((self.dmc.remaining > 0) as u8) << 4;
+        v |= (self.frame_irq as u8) << 6;
+        v |= (self.dmc.irq as u8) << 7;
+        // reading acknowledges the frame IRQ
+        self.frame_irq = false;
+        v
+    }
+
+    /// whether the apu is pulling the cpu's IRQ line low
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    /// the mixed level of all five channels, 0.0 to about 1.0
+    pub fn output(&self) -> f32 {
+        let pulses = (self.pulses[0].output() + self.pulses[1].output()) as f32;
+        let pulse_out = if pulses == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulses + 100.0)
+        };
+        let (t, n, d) = (
+            self.triangle.output() as f32,
+            self.noise.output() as f32,
+            self.dmc.output() as f32,
+        );
+        let tnd = t / 8227.0 + n / 12241.0 + d / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+        pulse_out + tnd_out
+    }
+
+    /// the address the DMC wants a sample byte from, if it does. The bus fetches it, stealing cpu cycles, and hands
+    /// it over with [Self::dmc_fill]
+    pub fn dmc_request(&mut self) -> Option<u16> {
+        self.dmc.request()
+    }
+
+    pub fn dmc_fill(&mut self, v: u8) {
+        self.dmc.fill(v);
+    }
+
+    /// once per cpu cycle
+    pub fn tick(&mut self) {
+        self.clock_frame_counter();
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        // the pulses' timers run at half the cpu clock
+        if self.cycles % 2 == 1 {
+            self.pulses.iter_mut().for_each(Pulse::clock_timer);
+        }
+        self.cycles += 1;
+    }
+
+    fn clock_frame_counter(&mut self) {
+        if let Some(n) = self.frame_reset.as_mut() {
+            *n -= 1;
+            if *n == 0 {
+                self.frame_reset = None;
+                self.frame_cycle = 0;
+                // entering 5 step mode clocks everything straight away
+                if self.five_step {
+                    self.quarter_frame();
+                    self.half_frame();
+                }
+                return;
+            }
+        }
+        self.frame_cycle += 1;
+        let (four, five) = self.region.frame_counter_steps();
+        let c = self.frame_cycle;
+        if self.five_step {
+            match c {
+                _ if c == five[0] || c == five[2] => self.quarter_frame(),
+                _ if c == five[1] || c == five[4] => {
+                    self.quarter_frame();
+                    self.half_frame();
+                }
+                _ if c == five[4] + 1 => self.frame_cycle = 0,
+                _ => (),
+            }
+            return;
+        }
+        match c {
+            _ if c == four[0] || c == four[2] => self.quarter_frame(),
+            _ if c == four[1] => {
+                self.quarter_frame();
+                self.half_frame();
+            }
+            // the IRQ flag goes up over three cycles around the last step
+            _ if c == four[3] - 1 => self.raise_frame_irq(),
+            _ if c == four[3] => {
+                self.raise_frame_irq();
+                self.quarter_frame();
+                self.half_frame();
+            }
+            _ if c == four[3] + 1 => {
+                self.raise_frame_irq();
+                self.frame_cycle = 0;
+            }
+            _ => (),
+        }
+    }
+
+    fn raise_frame_irq(&mut self) {
+        if !self.irq_inhibit {
+            self.frame_irq = true;
+        }
+    }
+
+    fn quarter_frame(&mut self) {
+        self.pulses.iter_mut().for_each(|p| p.envelope.clock());
+        self.noise.envelope.clock();
+        self.triangle.clock_linear();
+    }
+
+    fn half_frame(&mut self) {
+        self.pulses.iter_mut().for_each(Pulse::clock_half_frame);
+        self.triangle.length.clock();
+        self.noise.length.clock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(apu: &mut Apu, cycles: u32) {
+        for _ in 0..cycles {
+            apu.tick();
+        }
+    }
+
+    #[test]
+    fn length_counters() {
+        let mut apu = Apu::new();
+        apu.write(0x4017, 0x40);
+        apu.write(0x4015, 0x05);
+        // length index 1 is 254, index 3 is 2
+        apu.write(0x4003, 0x18);
+        apu.write(0x400b, 0x08);
+        apu.write(0x4007, 0x18);
+        assert_eq!(apu.read_status(), 0x05);
+        // two half frames in each 4 step sequence
+        run(&mut apu, 3 + 29830);
+        assert_eq!(apu.read_status(), 0x04);
+        // halted, the triangle keeps going; disabling it stops it
+        apu.write(0x4008, 0x80);
+        run(&mut apu, 29830 * 200);
+        assert_eq!(apu.read_status(), 0x04);
+        apu.write(0x4015, 0x00);
+        assert_eq!(apu.read_status(), 0x00);
+    }
+
+    #[test]
+    fn mixer() {
+        let mut apu = Apu::new();
+        // the triangle rests at 15
+        assert!((apu.output() - 0.2464).abs() < 0.001);
+        apu.write(0x4011, 127);
+        assert!((apu.output() - 0.6813).abs() < 0.001);
+        // the second channel on the same DAC adds less than the first: 95.88 / (8128 / n + 100) for n of 15 and 30,
+        // where adding them up would make it 0.2988
+        apu.write(0x4015, 0x03);
+        for addr in [0x4000, 0x4004] {
+            apu.write(addr, 0xdf);
+            apu.write(addr + 2, 0x80);
+            apu.write(addr + 3, 0x08);
+        }
+        let both = apu.output();
+        apu.write(0x4015, 0x01);
+        let one = apu.output();
+        apu.write(0x4015, 0x00);
+        let tnd = apu.output();
+        assert!((one - tnd - 0.1494).abs() < 0.001, "{}", one - tnd);
+        assert!((both - tnd - 0.2585).abs() < 0.001, "{}", both - tnd);
+    }
+
+    #[test]
+    fn frame_irq() {
+        let mut apu = Apu::new();
+        apu.write(0x4017, 0x00);
+        run(&mut apu, 3 + 29827);
+        assert!(!apu.irq());
+        run(&mut apu, 1);
+        assert!(apu.irq());
+        // reading clears it, but it comes back for the next two cycles
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        run(&mut apu, 1);
+        assert!(apu.irq());
+        apu.read_status();
+        run(&mut apu, 1);
+        assert!(apu.irq());
+        apu.read_status();
+        run(&mut apu, 29826);
+        assert!(!apu.irq());
+
+        // none in 5 step mode, and inhibiting clears it
+        apu.write(0x4017, 0x80);
+        run(&mut apu, 37282 * 2);
+        assert!(!apu.irq());
+        apu.write(0x4017, 0x00);
+        run(&mut apu, 30000);
+        assert!(apu.irq());
+        apu.write(0x4017, 0x40);
+        assert!(!apu.irq());
+    }
+}
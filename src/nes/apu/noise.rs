@@ -0,0 +1,98 @@
+//! The noise channel: the top bit of a 15 bit linear feedback shift register, through an envelope. In its normal
+//! mode the feedback comes from bits 0 and 1 and the sequence is 32767 steps long, a hiss; mode 1 takes bits 0 and 6,
+//! which gives loops of 93 or 31 steps, a metallic buzz.
+//! [reference](https://www.nesdev.org/wiki/APU_Noise)
+use super::{Envelope, Length};
+
+#[derive(Debug)]
+pub(crate) struct Noise {
+    pub(super) length: Length,
+    pub(super) envelope: Envelope,
+    short: bool,
+    period: u16,
+    timer: u16,
+    shift: u16,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self {
+            length: Length::default(),
+            envelope: Envelope::default(),
+            short: false,
+            period: 4,
+            timer: 0,
+            // it powers up with a 1 in it; all 0s would stay 0s
+            shift: 1,
+        }
+    }
+}
+
+impl Noise {
+    /// `periods` is the region's timer period table
+    pub(super) fn write(&mut self, reg: u16, v: u8, periods: &[u16; 16]) {
+        match reg {
+            0 => {
+                self.length.halt = v & 0x20 != 0;
+                self.envelope.write(v);
+            }
+            1 => (),
+            2 => {
+                self.short = v & 0x80 != 0;
+                self.period = periods[v as usize & 0x0f];
+            }
+            _ => {
+                self.length.load(v >> 3);
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    // every cpu cycle; the periods are in cpu cycles
+    pub(super) fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            let tap = if self.short { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+            self.shift = (self.shift >> 1) | feedback << 14;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// 0-15
+    pub(super) fn output(&self) -> u8 {
+        if self.shift & 1 != 0 || !self.length.active() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // how many steps the register takes to come back round
+    fn loop_length(short: bool) -> usize {
+        let mut n = Noise {
+            short,
+            period: 1,
+            ..Default::default()
+        };
+        n.clock_timer();
+        let start = n.shift;
+        (1..).find(|_| {
+            n.clock_timer();
+            n.shift == start
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn sequence_lengths() {
+        assert_eq!(loop_length(false), 32767);
+        assert_eq!(loop_length(true), 93);
+    }
+}
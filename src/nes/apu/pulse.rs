@@ -0,0 +1,125 @@
+//! The pulse channels: a square wave with four duty cycles, an envelope, and a sweep unit that bends the pitch up or
+//! down every half frame. The two differ in one thing: pulse 1's sweep negates with ones' complement, so sweeping
+//! down moves it one further than pulse 2.
+//! [reference](https://www.nesdev.org/wiki/APU_Pulse) and [sweep](https://www.nesdev.org/wiki/APU_Sweep)
+use super::{Envelope, Length};
+
+const DUTIES: [u8; 4] = [0b0100_0000, 0b0110_0000, 0b0111_1000, 0b1001_1111];
+
+#[derive(Debug, Default)]
+pub(crate) struct Pulse {
+    ones_complement: bool,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    pub(super) length: Length,
+    pub(super) envelope: Envelope,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_reload: bool,
+    sweep_divider: u8,
+}
+
+impl Pulse {
+    /// pulse 1 is `first`
+    pub(super) fn new(first: bool) -> Self {
+        Self {
+            ones_complement: first,
+            ..Default::default()
+        }
+    }
+
+    pub(super) fn write(&mut self, reg: u16, v: u8) {
+        match reg {
+            0 => {
+                self.duty = v >> 6;
+                self.length.halt = v & 0x20 != 0;
+                self.envelope.write(v);
+            }
+            1 => {
+                self.sweep_enabled = v & 0x80 != 0;
+                self.sweep_period = (v >> 4) & 0x07;
+                self.sweep_negate = v & 0x08 != 0;
+                self.sweep_shift = v & 0x07;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x700) | v as u16,
+            _ => {
+                self.period = (self.period & 0xff) | ((v as u16 & 0x07) << 8);
+                self.length.load(v >> 3);
+                self.step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    // once every apu cycle (two cpu cycles)
+    pub(super) fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 7;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // the period the sweep is heading for. It is worked out all the time, not just when the sweep is on
+    fn target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if self.sweep_negate {
+            self.period.saturating_sub(change + self.ones_complement as u16)
+        } else {
+            self.period + change
+        }
+    }
+
+    // periods under 8, or sweeps heading past $7FF, silence the channel
+    fn muted(&self) -> bool {
+        self.period < 8 || self.target() > 0x7ff
+    }
+
+    pub(super) fn clock_half_frame(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+        self.length.clock();
+    }
+
+    /// 0-15
+    pub(super) fn output(&self) -> u8 {
+        if self.muted() || !self.length.active() || DUTIES[self.duty as usize] & (0x80 >> self.step) == 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweep_negate() {
+        let mut pulses = [Pulse::new(true), Pulse::new(false)];
+        for p in pulses.iter_mut() {
+            p.length.set_enabled(true);
+            p.write(1, 0x89);
+            p.write(2, 0x00);
+            p.write(3, 0x01);
+            p.clock_half_frame();
+        }
+        // $100 less $80, and one more for pulse 1
+        assert_eq!((pulses[0].period, pulses[1].period), (0x7f, 0x80));
+    }
+}
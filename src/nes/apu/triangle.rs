@@ -0,0 +1,70 @@
+//! The triangle channel: a 32 step triangle wave with no volume control, only on or off. Besides the length counter
+//! it has a linear counter, a finer timer clocked every quarter frame, and it only steps while both are above 0, so
+//! stopping it leaves the wave where it was rather than dropping to 0.
+//! [reference](https://www.nesdev.org/wiki/APU_Triangle)
+use super::Length;
+
+#[derive(Debug, Default)]
+pub(crate) struct Triangle {
+    pub(super) length: Length,
+    // halts the length counter and keeps reloading the linear counter
+    control: bool,
+    linear_reload_value: u8,
+    linear: u8,
+    linear_reload: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+}
+
+impl Triangle {
+    pub(super) fn write(&mut self, reg: u16, v: u8) {
+        match reg {
+            0 => {
+                self.control = v & 0x80 != 0;
+                self.length.halt = self.control;
+                self.linear_reload_value = v & 0x7f;
+            }
+            1 => (),
+            2 => self.period = (self.period & 0x700) | v as u16,
+            _ => {
+                self.period = (self.period & 0xff) | ((v as u16 & 0x07) << 8);
+                self.length.load(v >> 3);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    // every cpu cycle
+    pub(super) fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length.active() && self.linear > 0 {
+                self.step = (self.step + 1) & 0x1f;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // on quarter frames
+    pub(super) fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear = self.linear_reload_value;
+        } else if self.linear > 0 {
+            self.linear -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    /// 0-15: down from 15 to 0, then back up
+    pub(super) fn output(&self) -> u8 {
+        if self.step < 16 {
+            15 - self.step
+        } else {
+            self.step - 16
+        }
+    }
+}
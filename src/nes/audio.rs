@@ -0,0 +1,280 @@
+//! Getting sound out of the console: from a level every cpu cycle (1.79MHz) to samples at a rate a sound card or a
+//! WAV file wants, and through the filters the console's own output stage puts on it.
+//!
+//! Taking every 40th level or so would alias everything above 22kHz back down into what we hear, and the apu makes
+//! plenty up there. Instead the levels are treated as steps, and each step is added to the output as a band-limited
+//! step: a windowed sinc, looked up by where between two samples the step falls, summed up. That is blargg's
+//! blip_buf idea, and costs a few multiplies per change of level rather than per cpu cycle.
+//! After that come the filters between the 2A03 and the AV jack: two high-passes (90Hz and 440Hz) and a low-pass at
+//! 14kHz, all first order.
+//! [reference](https://www.nesdev.org/wiki/APU_Mixer)
+use super::region::Region;
+use crate::wav::WavWriter;
+use std::f64::consts::PI;
+use std::io::{self, Seek, Write};
+
+// the band-limited step: this many output samples wide, at this many positions between two samples
+const TAPS: usize = 16;
+const PHASES: usize = 32;
+// samples handed to the sink at a time
+const CHUNK: usize = 512;
+
+/// Where the samples go
+pub trait AudioSink {
+    fn samples(&mut self, samples: &[f32]);
+}
+
+impl<F: FnMut(&[f32])> AudioSink for F {
+    fn samples(&mut self, samples: &[f32]) {
+        self(samples)
+    }
+}
+
+/// A sink that writes a WAV file. A write that fails stops the writing; [WavSink::error] says why
+pub struct WavSink<W: Write + Seek> {
+    wav: WavWriter<W>,
+    error: Option<io::Error>,
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    pub fn new(w: W, rate: u32) -> io::Result<Self> {
+        Ok(Self {
+            wav: WavWriter::new(w, rate)?,
+            error: None,
+        })
+    }
+
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+    fn samples(&mut self, samples: &[f32]) {
+        if self.error.is_none() {
+            self.error = self.wav.write(samples).err();
+        }
+    }
+}
+
+// turns levels at the cpu clock into band-limited samples at the output rate
+struct Resampler {
+    // output samples per cpu cycle
+    ratio: f64,
+    // where the next cpu cycle falls, in output samples from the start of `deltas`
+    time: f64,
+    level: f32,
+    // the steps' contributions to each output sample, to be summed up
+    deltas: Vec<f32>,
+    sum: f32,
+    kernel: Vec<[f32; TAPS]>,
+}
+
+impl Resampler {
+    fn new(ratio: f64) -> Self {
+        // a sinc a little under the output's Nyquist frequency, through a Blackman window, shifted by each phase
+        let cutoff = 0.45;
+        let kernel = (0..PHASES)
+            .map(|phase| {
+                let mut taps = [0.0; TAPS];
+                for (k, tap) in taps.iter_mut().enumerate() {
+                    let x = k as f64 - (TAPS / 2) as f64 + 1.0 - phase as f64 / PHASES as f64;
+                    let sinc = if x == 0.0 {
+                        1.0
+                    } else {
+                        (2.0 * PI * cutoff * x).sin() / (2.0 * PI * cutoff * x)
+                    };
+                    let w = 2.0 * PI * (x + (TAPS / 2) as f64) / TAPS as f64;
+                    let window = 0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+                    *tap = (sinc * window) as f32;
+                }
+                // each step adds up to exactly its size
+                let total: f32 = taps.iter().sum();
+                taps.map(|t| t / total)
+            })
+            .collect();
+        Self {
+            ratio,
+            time: 0.0,
+            level: 0.0,
+            deltas: vec![0.0; TAPS + 1],
+            sum: 0.0,
+            kernel,
+        }
+    }
+
+    // the level for one cpu cycle
+    fn push(&mut self, level: f32) {
+        if level != self.level {
+            let i = self.time as usize;
+            let phase = ((self.time - i as f64) * PHASES as f64) as usize;
+            let delta = level - self.level;
+            if self.deltas.len() < i + TAPS {
+                self.deltas.resize(i + TAPS, 0.0);
+            }
+            for (d, k) in self.deltas[i..i + TAPS].iter_mut().zip(&self.kernel[phase]) {
+                *d += delta * k;
+            }
+            self.level = level;
+        }
+        self.time += self.ratio;
+    }
+
+    // how many samples no step to come can change
+    fn ready(&self) -> usize {
+        self.time as usize
+    }
+
+    fn take(&mut self, out: &mut Vec<f32>) {
+        let n = self.ready();
+        if self.deltas.len() < n + TAPS {
+            self.deltas.resize(n + TAPS, 0.0);
+        }
+        for d in self.deltas.drain(..n) {
+            self.sum += d;
+            out.push(self.sum);
+        }
+        self.time -= n as f64;
+    }
+}
+
+#[derive(Debug, Default)]
+struct HighPass {
+    a: f32,
+    prev_in: f32,
+    prev_out: f32,
+}
+
+impl HighPass {
+    fn new(hz: f64, rate: f64) -> Self {
+        let rc = 1.0 / (2.0 * PI * hz);
+        Self {
+            a: (rc / (rc + 1.0 / rate)) as f32,
+            ..Default::default()
+        }
+    }
+
+    fn run(&mut self, x: f32) -> f32 {
+        self.prev_out = self.a * (self.prev_out + x - self.prev_in);
+        self.prev_in = x;
+        self.prev_out
+    }
+}
+
+#[derive(Debug, Default)]
+struct LowPass {
+    b: f32,
+    prev_out: f32,
+}
+
+impl LowPass {
+    fn new(hz: f64, rate: f64) -> Self {
+        let rc = 1.0 / (2.0 * PI * hz);
+        let dt = 1.0 / rate;
+        Self {
+            b: (dt / (rc + dt)) as f32,
+            ..Default::default()
+        }
+    }
+
+    fn run(&mut self, x: f32) -> f32 {
+        self.prev_out += self.b * (x - self.prev_out);
+        self.prev_out
+    }
+}
+
+/// Takes the mixed level every cpu cycle, and hands the sink filtered samples at `rate`, in chunks
+pub struct AudioOut {
+    rate: u32,
+    resampler: Resampler,
+    high: [HighPass; 2],
+    low: LowPass,
+    buf: Vec<f32>,
+    sink: Box<dyn AudioSink>,
+}
+
+impl AudioOut {
+    /// `rate` in Hz, e.g. 44100 or 48000
+    pub fn new(rate: u32, region: Region, sink: Box<dyn AudioSink>) -> Self {
+        let r = rate as f64;
+        Self {
+            rate,
+            resampler: Resampler::new(r / region.cpu_clock()),
+            high: [HighPass::new(90.0, r), HighPass::new(440.0, r)],
+            low: LowPass::new(14_000.0, r),
+            buf: Vec::with_capacity(CHUNK * 2),
+            sink,
+        }
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// the cpu clock the levels come at
+    pub fn set_region(&mut self, region: Region) {
+        self.resampler.ratio = self.rate as f64 / region.cpu_clock();
+    }
+
+    /// the level (0.0 to about 1.0) for one cpu cycle
+    pub fn push(&mut self, level: f32) {
+        self.resampler.push(level);
+        if self.resampler.ready() >= CHUNK {
+            self.flush();
+        }
+    }
+
+    /// hands the sink every sample that is ready
+    pub fn flush(&mut self) {
+        self.buf.clear();
+        self.resampler.take(&mut self.buf);
+        let [first, second] = &mut self.high;
+        for s in self.buf.iter_mut() {
+            *s = self.low.run(second.run(first.run(*s)));
+        }
+        if !self.buf.is_empty() {
+            self.sink.samples(&self.buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // a second of a square wave at `hz`, through an AudioOut at 44.1kHz
+    fn square(hz: f64) -> Vec<f32> {
+        let out = Rc::new(RefCell::new(vec![]));
+        let o = out.clone();
+        let mut audio = AudioOut::new(
+            44100,
+            Region::Ntsc,
+            Box::new(move |s: &[f32]| o.borrow_mut().extend_from_slice(s)),
+        );
+        let clock = Region::Ntsc.cpu_clock();
+        for i in 0..clock as usize {
+            let high = (i as f64 * hz / clock).fract() < 0.5;
+            audio.push(if high { 0.5 } else { 0.0 });
+        }
+        audio.flush();
+        out.take()
+    }
+
+    fn rms(s: &[f32]) -> f32 {
+        (s.iter().map(|v| v * v).sum::<f32>() / s.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn rate_and_filters() {
+        let a = square(1000.0);
+        assert!((44080..=44100).contains(&a.len()));
+        // centred by the high-passes, and a little under the square's 0.25, which the 440Hz one takes some of
+        let tail = &a[4410..];
+        assert!((tail.iter().sum::<f32>() / tail.len() as f32).abs() < 0.01);
+        assert!((0.2..0.25).contains(&rms(tail)));
+        // a square well above what 44.1kHz can hold does not alias down into a loud tone
+        assert!(rms(&square(30_000.0)[4410..]) < 0.01);
+    }
+}
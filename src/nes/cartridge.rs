@@ -0,0 +1,377 @@
+//! NES cartridges, as dumped to iNES and NES 2.0 files.
+//! A dump is a 16 byte header, an optional 512 byte trainer, then the PRG-ROM (the program, seen by the cpu) and
+//! the CHR-ROM (the tiles, seen by the ppu). The header says how big each part is, which board (mapper) the game was
+//! on, how the board wires the nametables, and whether there is battery-backed ram for saves.
+//! NES 2.0 is a backwards compatible extension of iNES that fills in what iNES left out: submappers, exact ram sizes,
+//! roms larger than 4MB, and the console and tv system the game was made for.
+//! [reference](https://www.nesdev.org/wiki/INES) and [NES 2.0](https://www.nesdev.org/wiki/NES_2.0)
+use std::{error::Error, fmt, fs, path::Path};
+
+const MAGIC: [u8; 4] = *b"NES\x1a";
+const HEADER_LEN: usize = 16;
+const TRAINER_LEN: usize = 512;
+const PRG_UNIT: usize = 0x4000;
+const CHR_UNIT: usize = 0x2000;
+
+/// Which header layout the file uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// the original format. Anything past byte 7 is unreliable, and old dumping tools put their names in bytes
+    /// 7-15, so for those only the low nibble of the mapper number can be trusted
+    INes,
+    Nes2,
+}
+
+/// How the 2KB of nametable ram inside the console is laid out over the ppu's four nametables.
+/// Normally the board hard-wires it, some mappers switch it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    /// $2000 = $2400 and $2800 = $2C00. For vertical scrolling
+    Horizontal,
+    /// $2000 = $2800 and $2400 = $2C00. For horizontal scrolling
+    Vertical,
+    /// the board carries 2KB more ram so all four nametables are distinct
+    FourScreen,
+    /// all four nametables show the first 1KB of ram
+    SingleLower,
+    /// all four nametables show the second 1KB of ram
+    SingleUpper,
+}
+
+impl Mirroring {
+    /// which 1KB page of nametable ram nametable `nt` (0-3, for $2000, $2400, $2800 and $2C00) shows.
+    /// Four-screen boards have four pages, everything else two
+    pub fn page(self, nt: u16) -> u16 {
+        match self {
+            Self::Horizontal => (nt >> 1) & 1,
+            Self::Vertical => nt & 1,
+            Self::FourScreen => nt & 3,
+            Self::SingleLower => 0,
+            Self::SingleUpper => 1,
+        }
+    }
+}
+
+/// The console the game was made for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    /// one of the clones and variants NES 2.0 enumerates in byte 13
+    Extended(u8),
+}
+
+/// The cpu/ppu timing, i.e. which tv system, the game expects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    /// RP2C02, North America and Japan
+    Ntsc,
+    /// RP2C07, most of Europe and Australia
+    Pal,
+    /// the game works on either
+    Multi,
+    /// UMC 6527P clones, Russia and former Soviet states
+    Dendy,
+}
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    /// fewer than 16 bytes
+    NoHeader,
+    /// the file does not start with `NES<EOF>`
+    BadMagic([u8; 4]),
+    /// the header asks for more data than the file holds
+    Truncated {
+        section: &'static str,
+        want: usize,
+        have: usize,
+    },
+    /// a size field that no real chip could have, e.g. an exponent that overflows
+    BadSize(&'static str),
+    /// the header names a board we do not emulate
+    UnsupportedMapper(u16),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartridgeError::NoHeader => write!(f, "file is shorter than the 16 byte iNES header"),
+            CartridgeError::BadMagic(m) => write!(f, "not an iNES file: starts with {:02X?} instead of \"NES\\x1A\"", m),
+            CartridgeError::Truncated { section, want, have } => write!(
+                f,
+                "file is truncated: header asks for {} bytes of {} but only {} are left",
+                want, section, have
+            ),
+            CartridgeError::BadSize(field) => write!(f, "header has an impossible {} size", field),
+            CartridgeError::UnsupportedMapper(n) => write!(f, "mapper {} is not supported", n),
+        }
+    }
+}
+
+impl Error for CartridgeError {}
+
+/// Everything the 16 byte header says about the cartridge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub format: Format,
+    /// in bytes
+    pub prg_rom_size: usize,
+    /// in bytes. 0 means the board has CHR-RAM instead
+    pub chr_rom_size: usize,
+    pub mapper: u16,
+    /// board variant within the mapper. Always 0 for iNES
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    /// the PRG-RAM (or some other memory) is kept alive by a battery and should be saved
+    pub battery: bool,
+    /// 512 bytes to be loaded at $7000 before the game starts
+    pub trainer: bool,
+    /// volatile PRG-RAM, in bytes
+    pub prg_ram_size: usize,
+    /// battery-backed PRG-RAM (or EEPROM), in bytes
+    pub prg_nvram_size: usize,
+    /// volatile CHR-RAM, in bytes
+    pub chr_ram_size: usize,
+    /// battery-backed CHR-RAM, in bytes
+    pub chr_nvram_size: usize,
+    pub console: Console,
+    pub timing: Timing,
+}
+
+impl Header {
+    pub fn parse(b: &[u8]) -> Result<Self, CartridgeError> {
+        if b.len() < HEADER_LEN {
+            return Err(CartridgeError::NoHeader);
+        }
+        let magic = [b[0], b[1], b[2], b[3]];
+        if magic != MAGIC {
+            return Err(CartridgeError::BadMagic(magic));
+        }
+        let (f6, f7) = (b[6], b[7]);
+        let format = if f7 & 0x0c == 0x08 {
+            Format::Nes2
+        } else {
+            Format::INes
+        };
+
+        let mirroring = if f6 & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if f6 & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let battery = f6 & 0x02 != 0;
+        let trainer = f6 & 0x04 != 0;
+        let console = match f7 & 0x03 {
+            0 => Console::Nes,
+            1 => Console::VsSystem,
+            2 => Console::Playchoice10,
+            _ if format == Format::Nes2 => Console::Extended(b[13] & 0x0f),
+            // iNES 1.0 only knows the first two bits individually
+            _ => Console::VsSystem,
+        };
+
+        if format == Format::Nes2 {
+            let mapper = (f6 >> 4) as u16 | (f7 & 0xf0) as u16 | ((b[8] & 0x0f) as u16) << 8;
+            let prg_rom_size = rom_size(b[4], b[9] & 0x0f, PRG_UNIT).ok_or(CartridgeError::BadSize("PRG-ROM"))?;
+            let chr_rom_size = rom_size(b[5], b[9] >> 4, CHR_UNIT).ok_or(CartridgeError::BadSize("CHR-ROM"))?;
+            let timing = match b[12] & 0x03 {
+                0 => Timing::Ntsc,
+                1 => Timing::Pal,
+                2 => Timing::Multi,
+                _ => Timing::Dendy,
+            };
+            Ok(Self {
+                format,
+                prg_rom_size,
+                chr_rom_size,
+                mapper,
+                submapper: b[8] >> 4,
+                mirroring,
+                battery,
+                trainer,
+                prg_ram_size: shift_size(b[10] & 0x0f),
+                prg_nvram_size: shift_size(b[10] >> 4),
+                chr_ram_size: shift_size(b[11] & 0x0f),
+                chr_nvram_size: shift_size(b[11] >> 4),
+                console,
+                timing,
+            })
+        } else {
+            // "DiskDude!" and friends wrote their signature over bytes 7-15. If the tail of the header is not clean,
+            // the upper mapper nibble is garbage
+            let clean = b[12..16].iter().all(|&x| x == 0);
+            let mapper = if clean {
+                (f6 >> 4) as u16 | (f7 & 0xf0) as u16
+            } else {
+                (f6 >> 4) as u16
+            };
+            let chr_rom_size = b[5] as usize * CHR_UNIT;
+            // byte 8 is PRG-RAM in 8KB units, with 0 meaning 8KB for compatibility. Boards without ram are rare
+            // enough that every emulator just assumes it is there
+            let prg_ram = (b[8].max(1) as usize) * 0x2000;
+            let (prg_ram_size, prg_nvram_size) = if battery { (0, prg_ram) } else { (prg_ram, 0) };
+            Ok(Self {
+                format,
+                prg_rom_size: b[4] as usize * PRG_UNIT,
+                chr_rom_size,
+                mapper,
+                submapper: 0,
+                mirroring,
+                battery,
+                trainer,
+                prg_ram_size,
+                prg_nvram_size,
+                chr_ram_size: if chr_rom_size == 0 { CHR_UNIT } else { 0 },
+                chr_nvram_size: 0,
+                console,
+                timing: if clean && b[9] & 0x01 != 0 {
+                    Timing::Pal
+                } else {
+                    Timing::Ntsc
+                },
+            })
+        }
+    }
+}
+
+// NES 2.0 rom sizes: a 12 bit count of units, unless the upper nibble is all ones, in which case the low byte is an
+// exponent-multiplier pair: 2^E * (MM*2 + 1) bytes
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> Option<usize> {
+    if msb == 0x0f {
+        let (e, mm) = ((lsb >> 2) as u32, (lsb & 0x03) as usize);
+        1usize.checked_shl(e)?.checked_mul(mm * 2 + 1)
+    } else {
+        Some(((msb as usize) << 8 | lsb as usize) * unit)
+    }
+}
+
+// NES 2.0 ram sizes: 0 for none, otherwise 64 << shift bytes
+fn shift_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+/// A game, ready to be plugged into the console
+#[derive(Debug, Clone)]
+pub struct Cartridge {
+    pub header: Header,
+    pub trainer: Option<Vec<u8>>,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    /// volatile and battery-backed PRG-RAM, one after the other
+    pub prg_ram: Vec<u8>,
+}
+
+impl Cartridge {
+    pub fn open<T: AsRef<Path>>(path: T) -> Result<Self, Box<dyn Error>> {
+        let b = fs::read(path)?;
+        Ok(Self::from_bytes(&b)?)
+    }
+
+    pub fn from_bytes(b: &[u8]) -> Result<Self, CartridgeError> {
+        let header = Header::parse(b)?;
+        let mut rest = &b[HEADER_LEN..];
+        let mut take = |section: &'static str, want: usize| {
+            if rest.len() < want {
+                return Err(CartridgeError::Truncated {
+                    section,
+                    want,
+                    have: rest.len(),
+                });
+            }
+            let (part, tail) = rest.split_at(want);
+            rest = tail;
+            Ok(part.to_vec())
+        };
+        let trainer = if header.trainer {
+            Some(take("trainer", TRAINER_LEN)?)
+        } else {
+            None
+        };
+        let prg_rom = take("PRG-ROM", header.prg_rom_size)?;
+        let chr_rom = take("CHR-ROM", header.chr_rom_size)?;
+        // anything left over (PlayChoice INST-ROM, misc roms) is not needed to run the game
+
+        let mut prg_ram = vec![0; header.prg_ram_size + header.prg_nvram_size];
+        if let Some(t) = &trainer {
+            // the trainer lives at $7000-$71FF, which is $1000 into PRG-RAM
+            if prg_ram.len() < 0x2000 {
+                prg_ram.resize(0x2000, 0);
+            }
+            prg_ram[0x1000..0x1000 + TRAINER_LEN].copy_from_slice(t);
+        }
+        Ok(Self {
+            header,
+            trainer,
+            prg_rom,
+            chr_rom,
+            prg_ram,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(bytes: &[u8]) -> [u8; 16] {
+        let mut h = [0u8; 16];
+        h[..4].copy_from_slice(&MAGIC);
+        h[4..4 + bytes.len()].copy_from_slice(bytes);
+        h
+    }
+
+    #[test]
+    fn ines() {
+        // 2x16KB PRG, 1x8KB CHR, mapper 0x41, vertical, battery
+        let h = Header::parse(&header(&[2, 1, 0x13, 0x40])).unwrap();
+        assert_eq!(h.format, Format::INes);
+        assert_eq!((h.prg_rom_size, h.chr_rom_size), (0x8000, 0x2000));
+        assert_eq!(h.mapper, 0x41);
+        assert_eq!(h.mirroring, Mirroring::Vertical);
+        assert!(h.battery);
+        assert_eq!((h.prg_ram_size, h.prg_nvram_size), (0, 0x2000));
+
+        // a dumper's signature in the tail means the high mapper nibble is junk
+        let mut b = header(&[2, 1, 0x10, 0x44]);
+        b[12..16].copy_from_slice(b"ude!");
+        assert_eq!(Header::parse(&b).unwrap().mapper, 1);
+    }
+
+    #[test]
+    fn nes2() {
+        // mapper 0x104 submapper 3, PAL, 8KB of PRG-NVRAM, 8KB CHR-RAM, PRG size as exponent-multiplier 2^20 * 3
+        let h = Header::parse(&header(&[0x51, 0, 0x42, 0x08, 0x31, 0x0f, 0x70, 0x07, 0x01])).unwrap();
+        assert_eq!(h.format, Format::Nes2);
+        assert_eq!((h.mapper, h.submapper), (0x104, 3));
+        assert_eq!(h.prg_rom_size, 3 << 20);
+        assert_eq!((h.prg_ram_size, h.prg_nvram_size), (0, 0x2000));
+        assert_eq!((h.chr_rom_size, h.chr_ram_size), (0, 0x2000));
+        assert_eq!(h.timing, Timing::Pal);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(Cartridge::from_bytes(b"NES"), Err(CartridgeError::NoHeader)));
+        assert!(matches!(
+            Cartridge::from_bytes(&[0u8; 16]),
+            Err(CartridgeError::BadMagic(_))
+        ));
+        let mut b = header(&[1, 1]).to_vec();
+        b.extend_from_slice(&[0; 0x4000]);
+        assert!(matches!(
+            Cartridge::from_bytes(&b),
+            Err(CartridgeError::Truncated {
+                section: "CHR-ROM",
+                want: 0x2000,
+                have: 0
+            })
+        ));
+    }
+}
@@ -0,0 +1,118 @@
+//! The controller ports.
+//! The cpu talks to whatever is plugged in through two registers. Writing $4016 sets three output lines shared by both
+//! ports, of which bit 0, the strobe, is the one controllers listen to. Reading $4016 or $4017 clocks the device in
+//! port 1 or 2 and takes what it puts on the data lines it is wired to; the rest of the byte is open bus.
+//!
+//! | address | read                                     | write                        |
+//! |---------|------------------------------------------|------------------------------|
+//! | $4016   | `xxxDDDDD` port 1 data, open bus above   | `.....OOO` outputs, 0 strobe |
+//! | $4017   | `xxxDDDDD` port 2 data, open bus above   | (the apu's frame counter)    |
+//!
+//! The standard controller is a shift register. While the strobe is high it keeps loading the state of its eight
+//! buttons, and reading it gives A; once the strobe goes low every read shifts out the next button, A, B, Select,
+//! Start, Up, Down, Left, Right, then 1s. Because every read clocks it, the DMC's repeated read while it fetches a
+//! sample eats a button, and games that read the pads while samples play have to read them twice and compare.
+//! [reference](https://www.nesdev.org/wiki/Standard_controller)
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// The button bits of [Joypad::set_buttons], in the order the controller shifts them out
+pub mod buttons {
+    pub const A: u8 = 0x01;
+    pub const B: u8 = 0x02;
+    pub const SELECT: u8 = 0x04;
+    pub const START: u8 = 0x08;
+    pub const UP: u8 = 0x10;
+    pub const DOWN: u8 = 0x20;
+    pub const LEFT: u8 = 0x40;
+    pub const RIGHT: u8 = 0x80;
+}
+
+/// A device in one of the controller ports
+pub trait ControllerPort {
+    /// a write to $4016, the three output lines in bits 0-2
+    fn write(&mut self, v: u8);
+
+    /// a read of the port's register. Only the low five bits reach the cpu; the device drives those it is wired to
+    /// and leaves the rest 0
+    fn read(&mut self) -> u8;
+}
+
+/// So the host can keep a handle on a device after plugging it in, to press its buttons
+impl<T: ControllerPort> ControllerPort for Rc<RefCell<T>> {
+    fn write(&mut self, v: u8) {
+        self.borrow_mut().write(v)
+    }
+
+    fn read(&mut self) -> u8 {
+        self.borrow_mut().read()
+    }
+}
+
+/// The standard controller
+#[derive(Debug, Default, Clone)]
+pub struct Joypad {
+    buttons: u8,
+    strobe: bool,
+    shift: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the buttons held down, from [buttons]. The host sets them once a frame, or whenever its input changes
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift = buttons;
+        }
+    }
+
+    pub fn buttons(&self) -> u8 {
+        self.buttons
+    }
+}
+
+impl ControllerPort for Joypad {
+    fn write(&mut self, v: u8) {
+        self.strobe = v & 0x01 != 0;
+        if self.strobe {
+            self.shift = self.buttons;
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.buttons & 0x01;
+        }
+        let bit = self.shift & 0x01;
+        // what shifts in behind the buttons is a 1
+        self.shift = (self.shift >> 1) | 0x80;
+        bit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shifts_out_buttons() {
+        let mut pad = Joypad::new();
+        pad.set_buttons(buttons::A | buttons::START | buttons::LEFT);
+        pad.write(1);
+        // strobe high: always A
+        assert_eq!((pad.read(), pad.read()), (1, 1));
+        pad.write(0);
+        let bits: Vec<u8> = (0..10).map(|_| pad.read()).collect();
+        assert_eq!(bits, [1, 0, 0, 1, 0, 0, 1, 0, 1, 1]);
+        // the buttons are only taken while the strobe is high
+        pad.set_buttons(0);
+        assert_eq!(pad.read(), 1);
+        pad.write(1);
+        pad.write(0);
+        assert_eq!(pad.read(), 0);
+    }
+}
@@ -0,0 +1,205 @@
+//! The discrete logic boards. No ASIC, just a 74-series latch on the data bus that catches any write to $8000-$FFFF
+//! and drives the high address lines of the PRG and CHR chips from it. Hundreds of games fit in these five:
+//!
+//! | mapper | board        | latch                                                          | games                     |
+//! |--------|--------------|----------------------------------------------------------------|---------------------------|
+//! | 2      | UxROM        | 16KB PRG bank at $8000, last bank fixed at $C000, CHR-RAM       | Mega Man, Castlevania     |
+//! | 3      | CNROM        | 8KB CHR bank, PRG fixed like NROM                               | Solomon's Key, Gradius    |
+//! | 7      | AxROM        | `...M.PPP`: 32KB PRG bank, single-screen nametable M            | Battletoads, Marble Madness |
+//! | 66     | GxROM        | `..PP..CC`: 32KB PRG bank, 8KB CHR bank                          | Super Mario Bros + Duck Hunt |
+//! | 11     | Color Dreams | `CCCC..PP`: 32KB PRG bank, 8KB CHR bank                          | Crystal Mines, Bible Adventures |
+//!
+//! The latch sits on the same data bus as the PRG-ROM, and nothing disables the rom during a write. So on most of
+//! these boards the cpu and the rom drive the bus at once and the latch sees the AND of the two: a bus conflict.
+//! Games avoid it by writing a value to a rom location that already holds that value. Whether a given board has
+//! them is a property of the board, not the mapper number, so it can be switched.
+//! [reference](https://www.nesdev.org/wiki/Bus_conflict)
+use super::{chr, Mapper};
+use crate::bus::bank::Banks;
+use crate::nes::cartridge::{Cartridge, Mirroring};
+
+/// which of the latch boards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Board {
+    UxRom,
+    CnRom,
+    AxRom,
+    GxRom,
+    ColorDreams,
+}
+
+impl Board {
+    /// the board an iNES mapper number names, if it is one of these
+    pub fn from_mapper(mapper: u16) -> Option<Self> {
+        match mapper {
+            2 => Some(Self::UxRom),
+            3 => Some(Self::CnRom),
+            7 => Some(Self::AxRom),
+            11 => Some(Self::ColorDreams),
+            66 => Some(Self::GxRom),
+            _ => None,
+        }
+    }
+
+    // the size of one PRG bank
+    fn prg_window(self) -> usize {
+        match self {
+            Self::UxRom | Self::CnRom => 0x4000,
+            _ => 0x8000,
+        }
+    }
+}
+
+pub struct Discrete {
+    board: Board,
+    prg: Banks,
+    chr: Banks,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+}
+
+impl Discrete {
+    pub fn new(board: Board, cart: Cartridge) -> Self {
+        let window = board.prg_window();
+        let mut prg = Banks::new(cart.prg_rom.clone(), window, 0x8000 / window);
+        if window == 0x4000 {
+            prg.switch(1, -1);
+        }
+        // NES 2.0 says whether the board has bus conflicts with submapper 1 (no) or 2 (yes), for the mappers that
+        // define submappers. Otherwise AxROM goes without them (ANROM and AOROM, the common ones, gate the rom) and
+        // the rest get them
+        let bus_conflicts = match cart.header.submapper {
+            1 if board != Board::GxRom && board != Board::ColorDreams => false,
+            2 if board != Board::GxRom && board != Board::ColorDreams => true,
+            _ => board != Board::AxRom,
+        };
+        let mirroring = match board {
+            Board::AxRom => Mirroring::SingleLower,
+            _ => cart.header.mirroring,
+        };
+        Self {
+            board,
+            prg,
+            chr: chr(&cart, 0x2000, 1),
+            mirroring,
+            bus_conflicts,
+        }
+    }
+
+    pub fn board(&self) -> Board {
+        self.board
+    }
+
+    /// whether writes to the latch get ANDed with the rom byte at the address written
+    pub fn set_bus_conflicts(&mut self, on: bool) {
+        self.bus_conflicts = on;
+    }
+
+    pub fn bus_conflicts(&self) -> bool {
+        self.bus_conflicts
+    }
+
+    fn latch(&mut self, v: u8) {
+        let (prg, chr) = match self.board {
+            Board::UxRom => (Some(v as isize), 0),
+            Board::CnRom => (None, v as isize),
+            Board::AxRom => {
+                self.mirroring = if v & 0x10 == 0 {
+                    Mirroring::SingleLower
+                } else {
+                    Mirroring::SingleUpper
+                };
+                (Some((v & 0x07) as isize), 0)
+            }
+            Board::GxRom => (Some(((v >> 4) & 0x03) as isize), (v & 0x03) as isize),
+            Board::ColorDreams => (Some((v & 0x03) as isize), (v >> 4) as isize),
+        };
+        if let Some(prg) = prg {
+            self.prg.switch(0, prg);
+        }
+        self.chr.switch(0, chr);
+    }
+}
+
+impl Mapper for Discrete {
+    fn cpu_load(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xffff => Some(self.prg.read(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_store(&mut self, addr: u16, v: u8) {
+        if addr >= 0x8000 {
+            let v = if self.bus_conflicts {
+                v & self.prg.read(addr as usize - 0x8000)
+            } else {
+                v
+            };
+            self.latch(v);
+        }
+    }
+
+    fn ppu_load(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize & 0x1fff)
+    }
+
+    fn ppu_store(&mut self, addr: u16, v: u8) {
+        self.chr.write(addr as usize & 0x1fff, v);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::mapper::test_cart;
+
+    // 16KB prg banks tagged with their number, and the byte at the end of each set to $FF so writes there are free of
+    // conflicts
+    fn cart(mapper: u16, prg_banks: usize, chr_banks: usize) -> Cartridge {
+        let mut c = test_cart(mapper, 0, prg_banks * 0x4000, 0x4000, chr_banks * 0x2000, 0x2000);
+        for bank in c.prg_rom.chunks_mut(0x4000) {
+            bank[0x3fff] = 0xff;
+        }
+        c
+    }
+
+    #[test]
+    fn uxrom_and_bus_conflicts() {
+        let c = cart(2, 8, 0);
+        let mut m = Discrete::new(Board::from_mapper(c.header.mapper).unwrap(), c);
+        assert_eq!(m.cpu_load(0xc000), Some(7));
+        m.cpu_store(0xbfff, 5);
+        assert_eq!(m.cpu_load(0x8000), Some(5));
+        // the rom under $8000 in bank 5 holds 5: 6 & 5 = 4
+        m.cpu_store(0x8000, 6);
+        assert_eq!(m.cpu_load(0x8000), Some(4));
+        m.set_bus_conflicts(false);
+        m.cpu_store(0x8000, 6);
+        assert_eq!(m.cpu_load(0x8000), Some(6));
+    }
+
+    #[test]
+    fn axrom_single_screen() {
+        let mut m = Discrete::new(Board::AxRom, cart(7, 8, 0));
+        assert_eq!(m.mirroring(), Mirroring::SingleLower);
+        m.cpu_store(0x8000, 0x12);
+        assert_eq!(m.mirroring(), Mirroring::SingleUpper);
+        // 32KB bank 2 is 16KB banks 4 and 5
+        assert_eq!((m.cpu_load(0x8000), m.cpu_load(0xc000)), (Some(4), Some(5)));
+    }
+
+    #[test]
+    fn gxrom_and_color_dreams_chr() {
+        let mut m = Discrete::new(Board::GxRom, cart(66, 4, 4));
+        m.cpu_store(0xffff, 0x13);
+        assert_eq!((m.cpu_load(0x8000), m.ppu_load(0)), (Some(2), 3));
+        let mut m = Discrete::new(Board::ColorDreams, cart(11, 4, 4));
+        m.cpu_store(0xffff, 0x21);
+        assert_eq!((m.cpu_load(0x8000), m.ppu_load(0)), (Some(2), 2));
+    }
+}
@@ -0,0 +1,276 @@
+//! MMC1, mapper 1. Nintendo's first ASIC mapper, on the SxROM boards: Zelda, Metroid, Kid Icarus, Final Fantasy.
+//! It has four 5-bit registers but only one data pin, so the cpu loads them serially: five writes anywhere in
+//! $8000-$FFFF, one bit each (bit 0 of the value, lsb first). The fifth write also picks the register, by bits 13-14
+//! of its address. A write with bit 7 set resets the shift register instead.
+//! The chip only looks at the first of two writes on consecutive cpu cycles. That matters because read-modify-write
+//! instructions write twice in a row, and games use `INC $FFFF` (on a byte holding $FF) as a one-instruction reset.
+//!
+//! | register       | bits                                                                    |
+//! |----------------|-------------------------------------------------------------------------|
+//! | control $8000  | `CPPMM`: mirroring MM, PRG bank mode PP, CHR bank mode C                |
+//! | CHR 0   $A000  | 4KB (or 8KB, low bit ignored) CHR bank at PPU $0000                      |
+//! | CHR 1   $C000  | 4KB CHR bank at PPU $1000, only in 4KB mode                              |
+//! | PRG     $E000  | `RPPPP`: 16KB PRG bank PPPP, PRG-RAM disabled when R is set (MMC1B)      |
+//!
+//! The larger boards reuse the high CHR bank bits, which a board with 8KB of CHR cannot use for CHR, for other
+//! things: SNROM gates the PRG-RAM with bit 4, SOROM and SXROM bank the PRG-RAM with bits 3 or 2-3, and SUROM and
+//! SXROM pick which 256KB half of a 512KB PRG-ROM is visible with bit 4. In 4KB CHR mode the bits come from
+//! whichever CHR register the ppu is using at the moment, which is whichever side of the pattern tables it last
+//! fetched from.
+//! [reference](https://www.nesdev.org/wiki/MMC1)
+use super::{chr, Mapper};
+use crate::bus::bank::Banks;
+use crate::nes::cartridge::{Cartridge, Mirroring};
+
+pub struct Mmc1 {
+    prg: Banks,
+    chr: Banks,
+    ram: Option<Banks>,
+    battery: bool,
+
+    shift: u8,
+    // number of bits shifted in so far
+    count: u8,
+    control: u8,
+    chr0: u8,
+    chr1: u8,
+    prg_reg: u8,
+
+    // cpu cycles since power on, and the cycle of the last write to $8000-$FFFF
+    cycle: u64,
+    last_write: Option<u64>,
+    // the ppu's A12 on its last pattern table fetch, which picks the chr register the board's extra lines follow
+    a12: bool,
+}
+
+impl Mmc1 {
+    pub fn new(cart: Cartridge) -> Self {
+        let ram = if cart.prg_ram.is_empty() {
+            None
+        } else {
+            // as many 8KB windows as the boards have: 8KB on most, 16KB on SOROM, 32KB on SXROM
+            Some(Banks::new(cart.prg_ram.clone(), 0x2000, 1).writable())
+        };
+        let mut m = Self {
+            prg: Banks::new(cart.prg_rom.clone(), 0x4000, 2),
+            chr: chr(&cart, 0x1000, 2),
+            ram,
+            battery: cart.header.battery,
+            shift: 0,
+            count: 0,
+            // power on in PRG mode 3, so the reset vector in the last bank is always there
+            control: 0x0c,
+            chr0: 0,
+            chr1: 0,
+            prg_reg: 0,
+            cycle: 0,
+            last_write: None,
+            a12: false,
+        };
+        m.update();
+        m
+    }
+
+    // the chr register that the board's extra address lines follow right now
+    fn outer(&self) -> u8 {
+        if self.control & 0x10 != 0 && self.a12 {
+            self.chr1
+        } else {
+            self.chr0
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        // SNROM: CHR-RAM only needs bit 0 of the bank, and bit 4 is wired to the PRG-RAM's second enable
+        let snrom = self.chr.is_writable() && self.prg.bank_count() <= 16 && self.outer() & 0x10 != 0;
+        self.prg_reg & 0x10 == 0 && !snrom
+    }
+
+    // recomputes the bank windows from the registers
+    fn update(&mut self) {
+        // 512KB boards: bit 4 selects the 256KB half. Everything below, fixed banks included, stays inside it
+        let outer = if self.prg.bank_count() > 16 {
+            (self.outer() & 0x10) as isize
+        } else {
+            0
+        };
+        let bank = (self.prg_reg & 0x0f) as isize;
+        let (lo, hi) = match (self.control >> 2) & 0x03 {
+            // 32KB at $8000, low bit of the bank number ignored
+            0 | 1 => (bank & !1, bank | 1),
+            // first bank fixed at $8000
+            2 => (0, bank),
+            // last bank fixed at $C000
+            _ => (bank, 0x0f),
+        };
+        self.prg.switch(0, outer | lo);
+        self.prg.switch(1, outer | hi);
+
+        if self.control & 0x10 == 0 {
+            self.chr.switch(0, (self.chr0 & 0x1e) as isize);
+            self.chr.switch(1, (self.chr0 | 0x01) as isize);
+        } else {
+            self.chr.switch(0, self.chr0 as isize);
+            self.chr.switch(1, self.chr1 as isize);
+        }
+
+        let outer = self.outer();
+        if let Some(ram) = self.ram.as_mut() {
+            // SOROM has two 8KB banks picked by bit 3, SXROM four picked by bits 2-3
+            let bank = match ram.bank_count() {
+                2 => (outer >> 3) & 0x01,
+                4 => (outer >> 2) & 0x03,
+                _ => 0,
+            };
+            ram.switch(0, bank as isize);
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, v: u8) {
+        match addr & 0x6000 {
+            0x0000 => self.control = v,
+            0x2000 => self.chr0 = v,
+            0x4000 => self.chr1 = v,
+            _ => self.prg_reg = v,
+        }
+        self.update();
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_load(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff if self.ram_enabled() => self.ram.as_ref().map(|r| r.read(addr as usize - 0x6000)),
+            0x8000..=0xffff => Some(self.prg.read(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_store(&mut self, addr: u16, v: u8) {
+        match addr {
+            0x6000..=0x7fff if self.ram_enabled() => {
+                if let Some(r) = self.ram.as_mut() {
+                    r.write(addr as usize - 0x6000, v);
+                }
+            }
+            0x8000..=0xffff => {
+                let cycle = self.cycle;
+                let consecutive = self.last_write.is_some_and(|c| cycle - c <= 1);
+                self.last_write = Some(self.cycle);
+                if consecutive {
+                    return;
+                }
+                if v & 0x80 != 0 {
+                    self.shift = 0;
+                    self.count = 0;
+                    self.control |= 0x0c;
+                    self.update();
+                    return;
+                }
+                self.shift |= (v & 0x01) << self.count;
+                self.count += 1;
+                if self.count == 5 {
+                    let v = self.shift;
+                    self.shift = 0;
+                    self.count = 0;
+                    self.write_register(addr, v);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn ppu_load(&mut self, addr: u16) -> u8 {
+        let a12 = addr & 0x1000 != 0;
+        if a12 != self.a12 {
+            self.a12 = a12;
+            if self.control & 0x10 != 0 {
+                self.update();
+            }
+        }
+        self.chr.read(addr as usize & 0x1fff)
+    }
+
+    fn ppu_store(&mut self, addr: u16, v: u8) {
+        self.chr.write(addr as usize & 0x1fff, v);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::SingleLower,
+            1 => Mirroring::SingleUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn cpu_tick(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn save_ram(&mut self) -> Option<&mut [u8]> {
+        match (self.battery, self.ram.as_mut()) {
+            (true, Some(r)) => Some(r.data_mut()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::mapper::test_cart;
+
+    // 16KB prg banks and 4KB chr banks tagged with their number
+    fn cart(prg_banks: usize, chr_banks: usize) -> Cartridge {
+        test_cart(1, 0, prg_banks * 0x4000, 0x4000, chr_banks * 0x2000, 0x1000)
+    }
+
+    // loads a register one bit per write, a few cycles apart like a real `STA`/`LSR A` sequence
+    fn load(m: &mut Mmc1, addr: u16, v: u8) {
+        for i in 0..5 {
+            m.cpu_store(addr, v >> i);
+            (0..4).for_each(|_| m.cpu_tick());
+        }
+    }
+
+    #[test]
+    fn serial_load_and_prg_modes() {
+        let mut m = Mmc1::new(cart(8, 2));
+        assert_eq!(m.cpu_load(0xc000), Some(7));
+        load(&mut m, 0xe000, 3);
+        assert_eq!(m.cpu_load(0x8000), Some(3));
+        // 32KB mode drops the low bit
+        load(&mut m, 0x8000, 0b00010);
+        assert_eq!((m.cpu_load(0x8000), m.cpu_load(0xc000)), (Some(2), Some(3)));
+        assert_eq!(m.mirroring(), Mirroring::Vertical);
+        // 4KB chr
+        load(&mut m, 0x8000, 0b10011);
+        load(&mut m, 0xc000, 1);
+        assert_eq!(m.ppu_load(0x1000), 1);
+    }
+
+    #[test]
+    fn consecutive_writes_ignored() {
+        let mut m = Mmc1::new(cart(8, 2));
+        m.cpu_store(0xe000, 1);
+        // the second write of an rmw lands on the next cycle and is dropped
+        m.cpu_tick();
+        m.cpu_store(0xe000, 0);
+        (0..4).for_each(|_| m.cpu_tick());
+        for _ in 0..4 {
+            m.cpu_store(0xe000, 0);
+            (0..4).for_each(|_| m.cpu_tick());
+        }
+        assert_eq!(m.cpu_load(0x8000), Some(1));
+    }
+
+    #[test]
+    fn surom_outer_bank() {
+        let mut m = Mmc1::new(cart(32, 0));
+        assert_eq!(m.cpu_load(0xc000), Some(15));
+        load(&mut m, 0xa000, 0x10);
+        assert_eq!(m.cpu_load(0xc000), Some(31));
+        assert_eq!(m.cpu_load(0x8000), Some(16));
+    }
+}
@@ -0,0 +1,188 @@
+//! MMC2 (mapper 9, PxROM: Punch-Out!!) and MMC4 (mapper 10, FxROM: Fire Emblem, Famicom Wars).
+//! Both give each 4KB half of the pattern tables two CHR bank registers and pick between them with a latch that the
+//! ppu sets by itself: fetching the tile at $xFD8 sets it to $FD, the tile at $xFE8 to $FE. A game puts a tile
+//! numbered $FD or $FE at the point on screen where it wants the graphics to change, and the switch happens without
+//! an IRQ. The new bank is used from the fetch after the one that triggered it.
+//! The only differences: the MMC2 has an 8KB switchable PRG bank and only looks at $0FD8/$0FE8 exactly on the low
+//! side, the MMC4 a 16KB one, PRG-RAM, and looks at the whole tile row on both sides.
+//!
+//! | register | bits                                            |
+//! |----------|-------------------------------------------------|
+//! | $A000    | PRG bank at $8000, rest fixed to the last banks |
+//! | $B000    | 4KB CHR bank at $0000 while latch 0 is $FD      |
+//! | $C000    | 4KB CHR bank at $0000 while latch 0 is $FE      |
+//! | $D000    | 4KB CHR bank at $1000 while latch 1 is $FD      |
+//! | $E000    | 4KB CHR bank at $1000 while latch 1 is $FE      |
+//! | $F000    | bit 0: 0 vertical, 1 horizontal mirroring       |
+//!
+//! [reference](https://www.nesdev.org/wiki/MMC2) and [MMC4](https://www.nesdev.org/wiki/MMC4)
+use super::{chr, prg_ram, Mapper};
+use crate::bus::bank::Banks;
+use crate::nes::cartridge::{Cartridge, Mirroring};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    Mmc2,
+    Mmc4,
+}
+
+pub struct Mmc2 {
+    chip: Chip,
+    prg: Banks,
+    chr: Banks,
+    ram: Option<Banks>,
+    battery: bool,
+    mirroring: Mirroring,
+    // the $FD and $FE banks for each half of the pattern tables
+    banks: [[u8; 2]; 2],
+    // per half, whether the latch holds $FE
+    latch: [bool; 2],
+}
+
+impl Mmc2 {
+    pub fn new(chip: Chip, cart: Cartridge) -> Self {
+        let mut prg = match chip {
+            Chip::Mmc2 => Banks::new(cart.prg_rom.clone(), 0x2000, 4),
+            Chip::Mmc4 => Banks::new(cart.prg_rom.clone(), 0x4000, 2),
+        };
+        let n = prg.windows();
+        for w in 1..n {
+            prg.switch(w, w as isize - n as isize);
+        }
+        let mut m = Self {
+            chip,
+            prg,
+            chr: chr(&cart, 0x1000, 2),
+            ram: prg_ram(&cart),
+            battery: cart.header.battery,
+            mirroring: cart.header.mirroring,
+            banks: [[0; 2]; 2],
+            latch: [true; 2],
+        };
+        m.update();
+        m
+    }
+
+    fn update(&mut self) {
+        for half in 0..2 {
+            let bank = self.banks[half][self.latch[half] as usize];
+            self.chr.switch(half, bank as isize);
+        }
+    }
+
+    // the latch a pattern fetch at `addr` sets, if any: (half, whether it is $FE)
+    fn snoop(&self, addr: u16) -> Option<(usize, bool)> {
+        let half = (addr >> 12) as usize & 1;
+        // the MMC2 decodes the full address on the low side, everything else only the tile
+        let exact = self.chip == Chip::Mmc2 && half == 0;
+        match (addr & 0x0ff8, addr & 0x0007) {
+            (0x0fd8, 0) => Some((half, false)),
+            (0x0fe8, 0) => Some((half, true)),
+            (0x0fd8, _) if !exact => Some((half, false)),
+            (0x0fe8, _) if !exact => Some((half, true)),
+            _ => None,
+        }
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn cpu_load(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff => self.ram.as_ref().map(|r| r.read(addr as usize - 0x6000)),
+            0x8000..=0xffff => Some(self.prg.read(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_store(&mut self, addr: u16, v: u8) {
+        match addr & 0xf000 {
+            0x6000 | 0x7000 => {
+                if let Some(r) = self.ram.as_mut() {
+                    r.write(addr as usize - 0x6000, v);
+                }
+            }
+            0xa000 => self.prg.switch(0, (v & 0x0f) as isize),
+            0xb000 => self.banks[0][0] = v & 0x1f,
+            0xc000 => self.banks[0][1] = v & 0x1f,
+            0xd000 => self.banks[1][0] = v & 0x1f,
+            0xe000 => self.banks[1][1] = v & 0x1f,
+            0xf000 => {
+                self.mirroring = if v & 0x01 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            _ => (),
+        }
+        self.update();
+    }
+
+    fn ppu_load(&mut self, addr: u16) -> u8 {
+        let v = self.chr.read(addr as usize & 0x1fff);
+        // the fetch itself still comes from the old bank
+        if let Some((half, fe)) = self.snoop(addr) {
+            self.latch[half] = fe;
+            self.update();
+        }
+        v
+    }
+
+    fn ppu_store(&mut self, addr: u16, v: u8) {
+        self.chr.write(addr as usize & 0x1fff, v);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_ram(&mut self) -> Option<&mut [u8]> {
+        match (self.battery, self.ram.as_mut()) {
+            (true, Some(r)) => Some(r.data_mut()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::mapper::test_cart;
+
+    // 8KB prg banks and 4KB chr banks tagged with their number
+    fn cart(mapper: u16) -> Cartridge {
+        test_cart(mapper, 0, 0x20000, 0x2000, 0x20000, 0x1000)
+    }
+
+    #[test]
+    fn mmc2_latches() {
+        let mut m = Mmc2::new(Chip::Mmc2, cart(9));
+        assert_eq!(m.cpu_load(0xa000), Some(13));
+        m.cpu_store(0xa000, 2);
+        assert_eq!(m.cpu_load(0x8000), Some(2));
+
+        m.cpu_store(0xb000, 4);
+        m.cpu_store(0xc000, 5);
+        m.cpu_store(0xd000, 6);
+        m.cpu_store(0xe000, 7);
+        assert_eq!((m.ppu_load(0x0000), m.ppu_load(0x1000)), (5, 7));
+        // the triggering fetch still sees the $FE bank
+        assert_eq!(m.ppu_load(0x0fd8), 5);
+        assert_eq!(m.ppu_load(0x0000), 4);
+        // the rest of the tile row does nothing on the low side of the MMC2
+        m.ppu_load(0x0fe9);
+        assert_eq!(m.ppu_load(0x0000), 4);
+        m.ppu_load(0x1fdd);
+        assert_eq!(m.ppu_load(0x1000), 6);
+    }
+
+    #[test]
+    fn mmc4_prg_and_row_latch() {
+        let mut m = Mmc2::new(Chip::Mmc4, cart(10));
+        m.cpu_store(0xa000, 1);
+        assert_eq!((m.cpu_load(0x8000), m.cpu_load(0xc000)), (Some(2), Some(14)));
+        m.cpu_store(0xb000, 4);
+        m.ppu_load(0x0fdf);
+        assert_eq!(m.ppu_load(0x0000), 4);
+    }
+}
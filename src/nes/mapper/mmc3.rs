@@ -0,0 +1,331 @@
+//! MMC3, mapper 4. The TxROM boards: Super Mario Bros 2 and 3, Kirby's Adventure, Mega Man 3-6.
+//! Eight bank registers, R0-R7, loaded through a select/data pair of ports, give 8KB PRG banks and 1KB/2KB CHR banks.
+//! The registers decode A0 and A13-A14 only, so each one repeats over its 8KB.
+//!
+//! | register               | bits                                                                       |
+//! |------------------------|----------------------------------------------------------------------------|
+//! | bank select  $8000     | `CP...RRR`: register R to load next, PRG mode P, CHR A12 inversion C       |
+//! | bank data    $8001     | the bank number for the selected register                                  |
+//! | mirroring    $A000     | bit 0: 0 vertical, 1 horizontal. Ignored on four-screen boards              |
+//! | PRG-RAM      $A001     | `EW......`: chip enable E, write protect W                                  |
+//! | IRQ latch    $C000     | the value the counter reloads with                                          |
+//! | IRQ reload   $C001     | clears the counter so the next clock reloads it                              |
+//! | IRQ disable  $E000     | disables the IRQ and acknowledges a pending one                              |
+//! | IRQ enable   $E001     |                                                                              |
+//!
+//! The scanline counter is clocked by rising edges of the ppu's A12. With backgrounds at $0000 and sprites at $1000
+//! (as nearly every MMC3 game has it) A12 rises once per rendered line, at the first sprite pattern fetch around dot
+//! 260, and that is where the IRQ fires. A12 also toggles within the sprite fetches, so the chip ignores a rise
+//! unless A12 has been low for a few cpu cycles.
+//! When a clock finds the counter at 0 (or a reload pending) the counter reloads from the latch, otherwise it counts
+//! down; if it is 0 afterwards and IRQs are enabled the IRQ line goes low. The Sharp MMC3B/C does that on every clock
+//! that leaves 0, so a latch of 0 fires on every line. The NEC MMC3A only fires when the counter got to 0 by counting
+//! down or by a reload asked for through $C001, so a latch of 0 fires once. blargg's `mmc3_test` checks both, and
+//! `tests/test_roms.rs` runs it when the ROMs are at hand.
+//! [reference](https://www.nesdev.org/wiki/MMC3)
+use super::{chr, prg_ram, Mapper};
+use crate::bus::bank::Banks;
+use crate::nes::cartridge::{Cartridge, Mirroring};
+
+// cpu cycles A12 has to stay low for the next rise to count
+const A12_FILTER: u64 = 3;
+
+/// which IRQ counter behaviour the chip has. See the module docs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Revision {
+    /// NEC MMC3A: no IRQ when the counter reloads with 0 by itself
+    A,
+    /// Sharp MMC3B and MMC3C: an IRQ on every clock that leaves the counter at 0
+    B,
+}
+
+pub struct Mmc3 {
+    prg: Banks,
+    chr: Banks,
+    ram: Option<Banks>,
+    battery: bool,
+    four_screen: bool,
+    revision: Revision,
+
+    select: u8,
+    regs: [u8; 8],
+    mirroring: Mirroring,
+    ram_protect: u8,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_line: bool,
+
+    cycle: u64,
+    // the cpu cycle A12 went low on, `None` while it is high
+    a12_low_since: Option<u64>,
+}
+
+impl Mmc3 {
+    pub fn new(cart: Cartridge) -> Self {
+        // NES 2.0 submapper 4 is the MMC3A. Everything else (and every iNES dump) gets the far more common MMC3B/C
+        let revision = match cart.header.submapper {
+            4 => Revision::A,
+            _ => Revision::B,
+        };
+        let mut m = Self {
+            prg: Banks::new(cart.prg_rom.clone(), 0x2000, 4),
+            chr: chr(&cart, 0x0400, 8),
+            ram: prg_ram(&cart),
+            battery: cart.header.battery,
+            four_screen: cart.header.mirroring == Mirroring::FourScreen,
+            revision,
+            select: 0,
+            regs: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: cart.header.mirroring,
+            ram_protect: 0x80,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_line: false,
+            cycle: 0,
+            a12_low_since: Some(0),
+        };
+        m.update();
+        m
+    }
+
+    /// switches the IRQ counter between the MMC3A and MMC3B behaviours, for dumps whose header does not say
+    pub fn set_revision(&mut self, revision: Revision) {
+        self.revision = revision;
+    }
+
+    pub fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    // recomputes the bank windows from the registers
+    fn update(&mut self) {
+        let r = |i: usize| self.regs[i] as isize;
+        // PRG mode 1 swaps $8000 and $C000: R6 moves up, the second to last bank moves down
+        let (p0, p2) = if self.select & 0x40 == 0 { (r(6), -2) } else { (-2, r(6)) };
+        self.prg.switch(0, p0);
+        self.prg.switch(1, r(7));
+        self.prg.switch(2, p2);
+        self.prg.switch(3, -1);
+
+        // R0 and R1 are 2KB banks (in 1KB units, low bit ignored), R2-R5 1KB banks. A12 inversion swaps the halves
+        let banks = [
+            r(0) & !1,
+            r(0) | 1,
+            r(1) & !1,
+            r(1) | 1,
+            r(2),
+            r(3),
+            r(4),
+            r(5),
+        ];
+        let flip = if self.select & 0x80 == 0 { 0 } else { 4 };
+        for (w, b) in banks.into_iter().enumerate() {
+            self.chr.switch(w ^ flip, b);
+        }
+    }
+
+    // clocks the scanline counter, on a filtered rising edge of A12
+    fn clock(&mut self) {
+        let before = self.irq_counter;
+        let reload = self.irq_reload;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+        self.irq_reload = false;
+
+        let fire = match self.revision {
+            Revision::A => (before > 0 || reload) && self.irq_counter == 0,
+            Revision::B => self.irq_counter == 0,
+        };
+        if fire && self.irq_enabled {
+            self.irq_line = true;
+        }
+    }
+
+    // follows A12 on everything the ppu puts on its address bus
+    fn watch_a12(&mut self, addr: u16) {
+        if addr & 0x1000 == 0 {
+            self.a12_low_since.get_or_insert(self.cycle);
+        } else if let Some(since) = self.a12_low_since.take() {
+            if self.cycle - since >= A12_FILTER {
+                self.clock();
+            }
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_protect & 0x80 != 0
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_load(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff if self.ram_enabled() => self.ram.as_ref().map(|r| r.read(addr as usize - 0x6000)),
+            0x8000..=0xffff => Some(self.prg.read(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_store(&mut self, addr: u16, v: u8) {
+        match (addr & 0xe001, addr) {
+            (_, 0x6000..=0x7fff) if self.ram_enabled() && self.ram_protect & 0x40 == 0 => {
+                if let Some(r) = self.ram.as_mut() {
+                    r.write(addr as usize - 0x6000, v);
+                }
+            }
+            (0x8000, _) => {
+                self.select = v;
+                self.update();
+            }
+            (0x8001, _) => {
+                self.regs[(self.select & 0x07) as usize] = v;
+                self.update();
+            }
+            (0xa000, _) if !self.four_screen => {
+                self.mirroring = if v & 0x01 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            (0xa001, _) => self.ram_protect = v,
+            (0xc000, _) => self.irq_latch = v,
+            (0xc001, _) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (0xe000, _) => {
+                self.irq_enabled = false;
+                self.irq_line = false;
+            }
+            (0xe001, _) => self.irq_enabled = true,
+            _ => (),
+        }
+    }
+
+    fn ppu_load(&mut self, addr: u16) -> u8 {
+        self.watch_a12(addr);
+        self.chr.read(addr as usize & 0x1fff)
+    }
+
+    fn ppu_store(&mut self, addr: u16, v: u8) {
+        self.watch_a12(addr);
+        self.chr.write(addr as usize & 0x1fff, v);
+    }
+
+    fn ppu_bus(&mut self, addr: u16) {
+        self.watch_a12(addr);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_line
+    }
+
+    fn cpu_tick(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn save_ram(&mut self) -> Option<&mut [u8]> {
+        match (self.battery, self.ram.as_mut()) {
+            (true, Some(r)) => Some(r.data_mut()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::mapper::test_cart;
+
+    // 8KB prg banks and 1KB chr banks tagged with their number
+    fn cart(prg_banks: usize, chr_banks: usize) -> Cartridge {
+        test_cart(4, 0, prg_banks * 0x4000, 0x2000, chr_banks * 0x2000, 0x0400)
+    }
+
+    // one rendered line as the counter sees it: background fetches from $0000, then sprite fetches from $1000
+    fn line(m: &mut Mmc3) {
+        for _ in 0..85 {
+            m.ppu_bus(0x0000);
+            m.cpu_tick();
+        }
+        for i in 0..8 {
+            m.ppu_bus(0x1000 + i * 16);
+            m.ppu_bus(0x2000);
+        }
+        m.cpu_tick();
+    }
+
+    #[test]
+    fn prg_and_chr_banking() {
+        let mut m = Mmc3::new(cart(8, 8));
+        assert_eq!(m.cpu_load(0xe000), Some(15));
+        assert_eq!(m.cpu_load(0xc000), Some(14));
+        m.cpu_store(0x8000, 0x06);
+        m.cpu_store(0x8001, 3);
+        assert_eq!(m.cpu_load(0x8000), Some(3));
+        // prg mode 1 swaps $8000 and $C000
+        m.cpu_store(0x8000, 0x40);
+        assert_eq!((m.cpu_load(0x8000), m.cpu_load(0xc000)), (Some(14), Some(3)));
+
+        m.cpu_store(0x8000, 0x00);
+        m.cpu_store(0x8001, 9);
+        assert_eq!((m.ppu_load(0x0000), m.ppu_load(0x0400)), (8, 9));
+        m.cpu_store(0x8000, 0x82);
+        m.cpu_store(0x8001, 20);
+        assert_eq!((m.ppu_load(0x0000), m.ppu_load(0x1000)), (20, 8));
+
+        m.cpu_store(0xa000, 1);
+        assert_eq!(m.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn scanline_irq() {
+        let mut m = Mmc3::new(cart(2, 1));
+        m.cpu_store(0xc000, 3);
+        m.cpu_store(0xc001, 0);
+        m.cpu_store(0xe001, 0);
+        // reload to 3, then 2, 1, 0
+        for _ in 0..3 {
+            line(&mut m);
+            assert!(!m.irq());
+        }
+        line(&mut m);
+        assert!(m.irq());
+        m.cpu_store(0xe000, 0);
+        assert!(!m.irq());
+    }
+
+    #[test]
+    fn zero_latch_revisions() {
+        for (revision, lines) in [(Revision::A, 1), (Revision::B, 3)] {
+            let mut m = Mmc3::new(cart(2, 1));
+            m.set_revision(revision);
+            m.cpu_store(0xc000, 0);
+            m.cpu_store(0xc001, 0);
+            m.cpu_store(0xe001, 0);
+            let mut fired = 0;
+            for _ in 0..3 {
+                line(&mut m);
+                if m.irq() {
+                    fired += 1;
+                    m.cpu_store(0xe000, 0);
+                    m.cpu_store(0xe001, 0);
+                }
+            }
+            assert_eq!(fired, lines, "{:?}", revision);
+        }
+    }
+}
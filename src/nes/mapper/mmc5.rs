@@ -0,0 +1,698 @@
+//! MMC5, mapper 5. Nintendo's biggest mapper, on the ExROM boards: Castlevania III, Just Breed, and the Koei strategy
+//! games (Uncharted Waters, Romance of the Three Kingdoms II, Nobunaga's Ambition II).
+//! Besides fine-grained banking it has 1KB of internal ram (ExRAM) that can serve as a fourth nametable, as per-tile
+//! attributes and CHR banks ("extended attributes") or as plain ram; a fill mode that makes a nametable read as one
+//! tile; a vertical split screen; an 8x8 bit multiplier; a scanline IRQ; and a sound chip with two pulse channels and
+//! an 8 bit PCM channel.
+//!
+//! | registers     |                                                                                         |
+//! |---------------|-----------------------------------------------------------------------------------------|
+//! | $5000-$5015   | audio: pulse 1 and 2 like the apu's $4000-$4003 minus the sweep, PCM, enables              |
+//! | $5100, $5101  | PRG mode (32/16/16+8/8KB banks), CHR mode (8/4/2/1KB banks)                                |
+//! | $5102, $5103  | PRG-RAM write protect: writes only go through while they hold 2 and 1                     |
+//! | $5104         | ExRAM mode: 0 nametable, 1 extended attributes, 2 ram, 3 read-only ram                     |
+//! | $5105         | nametable mapping, two bits per nametable: CIRAM page 0, page 1, ExRAM, fill               |
+//! | $5106, $5107  | fill mode tile and attribute                                                               |
+//! | $5113-$5117   | PRG banks: ram at $6000, then $8000-$FFFF. Bit 7 of $5114-$5116 picks rom over ram         |
+//! | $5120-$5130   | CHR banks: $5120-$5127 for sprites, $5128-$512B for backgrounds, $5130 the high bits       |
+//! | $5200-$5202   | vertical split: enable/side/column, scroll, CHR bank                                       |
+//! | $5203, $5204  | IRQ scanline compare, IRQ enable (write) and status (read)                                 |
+//! | $5205, $5206  | multiplier: factors on write, product (lo, hi) on read                                     |
+//! | $5C00-$5FFF   | ExRAM                                                                                      |
+//!
+//! The real chip works out where the ppu is by watching its fetches (three reads of the same nametable address mark
+//! the end of a line). We are told the dot directly with [Mapper::ppu_tick], which gives the same answers: which
+//! fetches are for sprites (dots 257-320) and which background tile column is being fetched. The writes to $2000 and
+//! $2001 that the chip decodes from the cpu bus tell it whether sprites are 8x16 and whether the ppu is rendering.
+//! [reference](https://www.nesdev.org/wiki/MMC5)
+use super::{chr, Mapper};
+use crate::bus::bank::Banks;
+use crate::nes::apu::LENGTHS;
+use crate::nes::cartridge::{Cartridge, Mirroring};
+
+const DUTIES: [u8; 4] = [0b0100_0000, 0b0110_0000, 0b0111_1000, 0b1001_1111];
+
+// the MMC5 has no frame counter of its own: envelopes and length counters are clocked at a fixed 240Hz
+const FRAME_PERIOD: u32 = 7457;
+
+/// A pulse channel of the MMC5: the apu's pulse without the sweep unit
+#[derive(Debug, Default)]
+struct Pulse {
+    enabled: bool,
+    duty: u8,
+    halt: bool,
+    constant: bool,
+    volume: u8,
+    period: u16,
+    timer: u16,
+    step: u8,
+    length: u8,
+    env_start: bool,
+    env_divider: u8,
+    env_decay: u8,
+}
+
+impl Pulse {
+    fn write(&mut self, reg: u16, v: u8) {
+        match reg & 3 {
+            0 => {
+                self.duty = v >> 6;
+                self.halt = v & 0x20 != 0;
+                self.constant = v & 0x10 != 0;
+                self.volume = v & 0x0f;
+            }
+            2 => self.period = (self.period & 0x700) | v as u16,
+            3 => {
+                self.period = (self.period & 0xff) | ((v as u16 & 0x07) << 8);
+                if self.enabled {
+                    self.length = LENGTHS[(v >> 3) as usize];
+                }
+                self.step = 0;
+                self.env_start = true;
+            }
+            _ => (),
+        }
+    }
+
+    fn set_enabled(&mut self, on: bool) {
+        self.enabled = on;
+        if !on {
+            self.length = 0;
+        }
+    }
+
+    // once every apu cycle (two cpu cycles)
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 7;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // envelope and length counter, at 240Hz
+    fn clock_frame(&mut self) {
+        if self.env_start {
+            self.env_start = false;
+            self.env_decay = 15;
+            self.env_divider = self.volume;
+        } else if self.env_divider == 0 {
+            self.env_divider = self.volume;
+            if self.env_decay > 0 {
+                self.env_decay -= 1;
+            } else if self.halt {
+                self.env_decay = 15;
+            }
+        } else {
+            self.env_divider -= 1;
+        }
+        if !self.halt && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || DUTIES[self.duty as usize] & (0x80 >> self.step) == 0 {
+            0
+        } else if self.constant {
+            self.volume
+        } else {
+            self.env_decay
+        }
+    }
+}
+
+pub struct Mmc5 {
+    prg: Banks,
+    // 8KB windows: $6000, then $8000-$FFFF
+    ram: Option<Banks>,
+    // the sprite set in windows 0-7, the background set in windows 8-15
+    chr: Banks,
+    battery: bool,
+    exram: [u8; 0x400],
+
+    prg_mode: u8,
+    chr_mode: u8,
+    ram_protect: [u8; 2],
+    exram_mode: u8,
+    nt_map: u8,
+    fill_tile: u8,
+    fill_attr: u8,
+    prg_regs: [u8; 5],
+    // whether each of the four windows at $8000-$FFFF shows rom
+    prg_rom: [bool; 4],
+    chr_regs: [u16; 12],
+    chr_high: u8,
+    // whether the background set ($5128-$512B) was written after the sprite set
+    chr_last_b: bool,
+
+    split_ctrl: u8,
+    split_scroll: u8,
+    split_bank: u8,
+
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    line: u8,
+
+    factors: [u8; 2],
+
+    // what the cpu told the ppu: 8x16 sprites, rendering on
+    tall_sprites: bool,
+    rendering: bool,
+    scanline: u16,
+    // the line before 0, which depends on the region; learnt as the ppu wraps round
+    pre_render: u16,
+    dot: u16,
+    // the ExRAM byte behind the background tile being fetched, and whether the tile is inside the split
+    tile_ex: u8,
+    in_split: bool,
+
+    pulses: [Pulse; 2],
+    pcm: u8,
+    pcm_read: bool,
+    pcm_irq_enabled: bool,
+    pcm_irq: bool,
+    odd: bool,
+    frame_divider: u32,
+}
+
+impl Mmc5 {
+    pub fn new(cart: Cartridge) -> Self {
+        let ram = if cart.prg_ram.is_empty() {
+            None
+        } else {
+            Some(Banks::new(cart.prg_ram.clone(), 0x2000, 5).writable())
+        };
+        let mut m = Self {
+            prg: Banks::new(cart.prg_rom.clone(), 0x2000, 4),
+            ram,
+            chr: chr(&cart, 0x0400, 16),
+            battery: cart.header.battery,
+            exram: [0; 0x400],
+            prg_mode: 3,
+            chr_mode: 0,
+            ram_protect: [0; 2],
+            exram_mode: 0,
+            nt_map: 0,
+            fill_tile: 0,
+            fill_attr: 0,
+            prg_regs: [0, 0, 0, 0, 0xff],
+            prg_rom: [true; 4],
+            chr_regs: [0; 12],
+            chr_high: 0,
+            chr_last_b: false,
+            split_ctrl: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            line: 0,
+            factors: [0xff; 2],
+            tall_sprites: false,
+            rendering: false,
+            scanline: 0,
+            pre_render: 261,
+            dot: 0,
+            tile_ex: 0,
+            in_split: false,
+            pulses: Default::default(),
+            pcm: 0,
+            pcm_read: false,
+            pcm_irq_enabled: false,
+            pcm_irq: false,
+            odd: false,
+            frame_divider: 0,
+        };
+        m.update_prg();
+        m.update_chr();
+        m
+    }
+
+    fn update_prg(&mut self) {
+        let regs = self.prg_regs;
+        let r = |i: usize| regs[i];
+        // (register, 8KB bank within it) for each window at $8000-$FFFF
+        let map: [(usize, u8, u8); 4] = match self.prg_mode {
+            0 => [(4, 0x7c, 0), (4, 0x7c, 1), (4, 0x7c, 2), (4, 0x7c, 3)],
+            1 => [(2, 0x7e, 0), (2, 0x7e, 1), (4, 0x7e, 0), (4, 0x7e, 1)],
+            2 => [(2, 0x7e, 0), (2, 0x7e, 1), (3, 0x7f, 0), (4, 0x7f, 0)],
+            _ => [(1, 0x7f, 0), (2, 0x7f, 0), (3, 0x7f, 0), (4, 0x7f, 0)],
+        };
+        for (w, (reg, mask, i)) in map.into_iter().enumerate() {
+            let bank = ((r(reg) & mask) | i) as isize;
+            // $5117 always maps rom
+            self.prg_rom[w] = reg == 4 || r(reg) & 0x80 != 0;
+            if self.prg_rom[w] {
+                self.prg.switch(w, bank);
+            } else if let Some(ram) = self.ram.as_mut() {
+                ram.switch(w + 1, bank);
+            }
+        }
+        if let Some(ram) = self.ram.as_mut() {
+            ram.switch(0, (r(0) & 0x7f) as isize);
+        }
+    }
+
+    fn update_chr(&mut self) {
+        let high = (self.chr_high as u16 & 0x03) << 8;
+        let regs = self.chr_regs;
+        let r = |i: usize| (regs[i] | high) as isize;
+        // the register and the 1KB bank within it for each 1KB window, sprites and then backgrounds
+        let (size, a, b): (isize, [usize; 8], [usize; 8]) = match self.chr_mode {
+            0 => (8, [7; 8], [11; 8]),
+            1 => (4, [3, 3, 3, 3, 7, 7, 7, 7], [11; 8]),
+            2 => (2, [1, 1, 3, 3, 5, 5, 7, 7], [9, 9, 11, 11, 9, 9, 11, 11]),
+            _ => (1, [0, 1, 2, 3, 4, 5, 6, 7], [8, 9, 10, 11, 8, 9, 10, 11]),
+        };
+        for w in 0..8 {
+            let i = w as isize % size;
+            self.chr.switch(w, r(a[w]) * size + i);
+            self.chr.switch(w + 8, r(b[w]) * size + i);
+        }
+    }
+
+    fn ram_writable(&self) -> bool {
+        self.ram_protect == [2, 1]
+    }
+
+    // the background tile column a nametable fetch at the current dot is for, if it is a background fetch at all
+    fn tile_column(&self) -> Option<u16> {
+        match self.dot {
+            1..=256 => Some((self.dot - 1) / 8 + 2),
+            321..=337 => Some((self.dot - 321) / 8),
+            _ => None,
+        }
+    }
+
+    // the line the background fetches at the current dot belong to
+    fn fetch_line(&self) -> u16 {
+        if self.dot >= 321 {
+            if self.scanline == self.pre_render {
+                0
+            } else {
+                self.scanline + 1
+            }
+        } else {
+            self.scanline
+        }
+    }
+
+    // the split screen's line for the current fetch, scrolled
+    fn split_line(&self) -> u16 {
+        (self.split_scroll as u16 + self.fetch_line()) % 240
+    }
+
+    fn sprite_fetch(&self) -> bool {
+        (257..=320).contains(&self.dot)
+    }
+
+    // whether a pattern fetch goes through the sprite set (windows 0-7) or the background set (8-15)
+    fn chr_set_b(&self) -> bool {
+        if self.tall_sprites && self.rendering && self.scanline < 240 {
+            !self.sprite_fetch()
+        } else {
+            self.chr_last_b
+        }
+    }
+
+    fn chr_byte(&self, offset: usize) -> u8 {
+        let data = self.chr.data();
+        data[offset % data.len()]
+    }
+
+    fn read_register(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5010 => {
+                let v = (self.pcm_irq as u8) << 7 | self.pcm_read as u8;
+                self.pcm_irq = false;
+                Some(v)
+            }
+            0x5015 => Some((self.pulses[0].length > 0) as u8 | ((self.pulses[1].length > 0) as u8) << 1),
+            0x5204 => {
+                let v = (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6;
+                self.irq_pending = false;
+                Some(v)
+            }
+            0x5205 | 0x5206 => {
+                let p = self.factors[0] as u16 * self.factors[1] as u16;
+                Some(if addr == 0x5205 { p as u8 } else { (p >> 8) as u8 })
+            }
+            0x5c00..=0x5fff if self.exram_mode >= 2 => Some(self.exram[addr as usize - 0x5c00]),
+            _ => None,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, v: u8) {
+        match addr {
+            0x5000..=0x5003 => self.pulses[0].write(addr, v),
+            0x5004..=0x5007 => self.pulses[1].write(addr, v),
+            0x5010 => {
+                self.pcm_read = v & 0x01 != 0;
+                self.pcm_irq_enabled = v & 0x80 != 0;
+            }
+            // writing 0 is ignored; in hardware a 0 is what raises the IRQ in read mode
+            0x5011 if !self.pcm_read && v != 0 => self.pcm = v,
+            0x5015 => {
+                self.pulses[0].set_enabled(v & 0x01 != 0);
+                self.pulses[1].set_enabled(v & 0x02 != 0);
+            }
+            0x5100 => {
+                self.prg_mode = v & 0x03;
+                self.update_prg();
+            }
+            0x5101 => {
+                self.chr_mode = v & 0x03;
+                self.update_chr();
+            }
+            0x5102 | 0x5103 => self.ram_protect[addr as usize - 0x5102] = v & 0x03,
+            0x5104 => self.exram_mode = v & 0x03,
+            0x5105 => self.nt_map = v,
+            0x5106 => self.fill_tile = v,
+            0x5107 => self.fill_attr = (v & 0x03) * 0x55,
+            0x5113..=0x5117 => {
+                self.prg_regs[addr as usize - 0x5113] = v;
+                self.update_prg();
+            }
+            0x5120..=0x512b => {
+                let i = addr as usize - 0x5120;
+                self.chr_regs[i] = v as u16;
+                self.chr_last_b = i >= 8;
+                self.update_chr();
+            }
+            0x5130 => {
+                self.chr_high = v;
+                self.update_chr();
+            }
+            0x5200 => self.split_ctrl = v,
+            0x5201 => self.split_scroll = v,
+            0x5202 => self.split_bank = v,
+            0x5203 => self.irq_compare = v,
+            0x5204 => self.irq_enabled = v & 0x80 != 0,
+            0x5205 | 0x5206 => self.factors[addr as usize - 0x5205] = v,
+            0x5c00..=0x5fff => {
+                let i = addr as usize - 0x5c00;
+                match self.exram_mode {
+                    // as a nametable or attributes ExRAM belongs to the ppu while it renders, the cpu gets it otherwise
+                    0 | 1 => self.exram[i] = if self.in_frame { v } else { 0 },
+                    2 => self.exram[i] = v,
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // start of a rendered line: the chip's scanline counter
+    fn new_line(&mut self) {
+        if self.in_frame {
+            self.line = self.line.wrapping_add(1);
+            if self.line == self.irq_compare {
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.line = 0;
+            self.irq_pending = false;
+        }
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_load(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5000..=0x5fff => self.read_register(addr),
+            0x6000..=0x7fff => self.ram.as_ref().map(|r| r.read(addr as usize - 0x6000)),
+            0x8000..=0xffff => {
+                let w = (addr as usize - 0x8000) >> 13;
+                let offset = addr as usize - 0x6000;
+                let v = if self.prg_rom[w] {
+                    Some(self.prg.read(addr as usize - 0x8000))
+                } else {
+                    self.ram.as_ref().map(|r| r.read(offset))
+                };
+                // PCM read mode samples whatever the cpu reads from $8000-$BFFF
+                if let (true, Some(v), 0x8000..=0xbfff) = (self.pcm_read, v, addr) {
+                    if v == 0 {
+                        self.pcm_irq = true;
+                    } else {
+                        self.pcm = v;
+                    }
+                }
+                v
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_store(&mut self, addr: u16, v: u8) {
+        match addr {
+            0x5000..=0x5fff => self.write_register(addr, v),
+            0x6000..=0xffff if self.ram_writable() => {
+                let w = (addr as usize).saturating_sub(0x8000) >> 13;
+                if addr < 0x8000 || !self.prg_rom[w] {
+                    if let Some(r) = self.ram.as_mut() {
+                        r.write(addr as usize - 0x6000, v);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn cpu_bus(&mut self, addr: u16, v: u8) {
+        match addr {
+            0x2000..=0x3fff if addr & 0x07 == 0 => self.tall_sprites = v & 0x20 != 0,
+            0x2000..=0x3fff if addr & 0x07 == 1 => {
+                self.rendering = v & 0x18 != 0;
+                if !self.rendering {
+                    self.in_frame = false;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn ppu_load(&mut self, addr: u16) -> u8 {
+        let bg = self.rendering && (self.scanline < 240 || self.scanline == self.pre_render) && !self.sprite_fetch();
+        if bg && self.in_split {
+            let fine = self.split_line() & 7;
+            let offset = (self.split_bank as usize) << 12 | (addr as usize & 0x0ff8) | fine as usize;
+            return self.chr_byte(offset);
+        }
+        if bg && self.exram_mode == 1 {
+            let bank = (self.tile_ex & 0x3f) as usize | (self.chr_high as usize & 0x03) << 6;
+            return self.chr_byte(bank << 12 | (addr as usize & 0x0fff));
+        }
+        let set = if self.chr_set_b() { 0x2000 } else { 0 };
+        self.chr.read(set | (addr as usize & 0x1fff))
+    }
+
+    fn ppu_store(&mut self, addr: u16, v: u8) {
+        let set = if self.chr_set_b() { 0x2000 } else { 0 };
+        self.chr.write(set | (addr as usize & 0x1fff), v);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        // only an approximation: $5105 can map the four nametables any way it likes. See `ciram_page`
+        match self.nt_map {
+            0x44 => Mirroring::Vertical,
+            0x50 => Mirroring::Horizontal,
+            0x55 => Mirroring::SingleUpper,
+            _ => Mirroring::SingleLower,
+        }
+    }
+
+    fn ciram_page(&self, nt: u16) -> u16 {
+        (self.nt_map as u16 >> (nt * 2)) & 1
+    }
+
+    fn nt_load(&mut self, addr: u16) -> Option<u8> {
+        let offset = addr as usize & 0x3ff;
+        let attr = offset >= 0x3c0;
+        let column = self.tile_column().filter(|_| self.rendering);
+
+        if !attr {
+            self.in_split = false;
+            if let Some(col) = column {
+                let split = self.split_ctrl as u16 & 0x1f;
+                let right = self.split_ctrl & 0x40 != 0;
+                self.in_split = self.split_ctrl & 0x80 != 0
+                    && self.exram_mode <= 1
+                    && if right { col >= split } else { col < split };
+            }
+            self.tile_ex = self.exram[offset];
+        }
+        if self.in_split && column.is_some() {
+            let (row, col) = (self.split_line() / 8, self.tile_column().unwrap_or(0) % 32);
+            return Some(if attr {
+                let a = self.exram[0x3c0 + (row as usize / 4) * 8 + col as usize / 4];
+                let shift = (row & 2) << 1 | (col & 2);
+                ((a >> shift) & 0x03) * 0x55
+            } else {
+                self.exram[row as usize * 32 + col as usize]
+            });
+        }
+        if attr && self.exram_mode == 1 && column.is_some() {
+            return Some((self.tile_ex >> 6) * 0x55);
+        }
+
+        let nt = (addr >> 10) & 0x03;
+        match (self.nt_map >> (nt * 2)) & 0x03 {
+            2 => Some(if self.exram_mode <= 1 { self.exram[offset] } else { 0 }),
+            3 => Some(if attr { self.fill_attr } else { self.fill_tile }),
+            _ => None,
+        }
+    }
+
+    fn nt_store(&mut self, addr: u16, v: u8) -> bool {
+        let nt = (addr >> 10) & 0x03;
+        match (self.nt_map >> (nt * 2)) & 0x03 {
+            2 => {
+                if self.exram_mode <= 1 {
+                    self.exram[addr as usize & 0x3ff] = v;
+                }
+                true
+            }
+            3 => true,
+            _ => false,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        (self.irq_pending && self.irq_enabled) || (self.pcm_irq && self.pcm_irq_enabled)
+    }
+
+    fn audio(&self) -> f32 {
+        let pulse = (self.pulses[0].output() + self.pulses[1].output()) as f32;
+        let pulse = if pulse == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulse + 100.0) };
+        // the PCM channel is about as loud as the apu's DMC would be at half the value
+        let pcm = self.pcm as f32 / 2.0;
+        let pcm = if pcm == 0.0 { 0.0 } else { 159.79 / (22638.0 / pcm + 100.0) };
+        pulse + pcm
+    }
+
+    fn cpu_tick(&mut self) {
+        self.odd = !self.odd;
+        if self.odd {
+            self.pulses.iter_mut().for_each(Pulse::clock_timer);
+        }
+        self.frame_divider += 1;
+        if self.frame_divider == FRAME_PERIOD {
+            self.frame_divider = 0;
+            self.pulses.iter_mut().for_each(Pulse::clock_frame);
+        }
+    }
+
+    fn ppu_tick(&mut self, scanline: u16, dot: u16) {
+        if scanline == 0 && self.scanline != 0 {
+            self.pre_render = self.scanline;
+        }
+        self.scanline = scanline;
+        self.dot = dot;
+        if dot == 2 && self.rendering {
+            match scanline {
+                0..=239 => self.new_line(),
+                240 => self.in_frame = false,
+                _ => (),
+            }
+        }
+    }
+
+    fn save_ram(&mut self) -> Option<&mut [u8]> {
+        match (self.battery, self.ram.as_mut()) {
+            (true, Some(r)) => Some(r.data_mut()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::mapper::test_cart;
+
+    // 8KB prg banks and 1KB chr banks tagged with their number, and 64KB of PRG-RAM
+    fn cart() -> Cartridge {
+        let mut c = test_cart(5, 0, 0x20000, 0x2000, 0x10000, 0x0400);
+        c.prg_ram = vec![0; 0x10000];
+        c
+    }
+
+    #[test]
+    fn prg_modes_and_ram() {
+        let mut m = Mmc5::new(cart());
+        assert_eq!(m.cpu_load(0xe000), Some(15));
+        m.cpu_store(0x5100, 1);
+        m.cpu_store(0x5115, 0x84);
+        assert_eq!((m.cpu_load(0x8000), m.cpu_load(0xa000)), (Some(4), Some(5)));
+        // ram at $8000 only takes writes once the protect registers are unlocked
+        m.cpu_store(0x5115, 0x00);
+        m.cpu_store(0x8000, 0x42);
+        assert_eq!(m.cpu_load(0x8000), Some(0));
+        m.cpu_store(0x5102, 2);
+        m.cpu_store(0x5103, 1);
+        m.cpu_store(0x8000, 0x42);
+        assert_eq!(m.cpu_load(0x8000), Some(0x42));
+        assert_eq!(m.cpu_load(0x6000), Some(0x42));
+    }
+
+    #[test]
+    fn multiplier_fill_and_exram() {
+        let mut m = Mmc5::new(cart());
+        m.cpu_store(0x5205, 200);
+        m.cpu_store(0x5206, 3);
+        assert_eq!((m.cpu_load(0x5205), m.cpu_load(0x5206)), (Some(600u16 as u8), Some(2)));
+
+        // nametable 1 is fill mode, nametable 2 ExRAM
+        m.cpu_store(0x5105, 0b00_10_11_00);
+        m.cpu_store(0x5106, 0x24);
+        m.cpu_store(0x5107, 0x02);
+        assert_eq!((m.nt_load(0x2400), m.nt_load(0x27c0)), (Some(0x24), Some(0xaa)));
+        assert_eq!(m.nt_load(0x2000), None);
+        assert!(m.nt_store(0x2805, 0x99));
+        assert_eq!(m.nt_load(0x2805), Some(0x99));
+        assert_eq!((m.ciram_page(0), m.ciram_page(3)), (0, 0));
+
+        m.cpu_store(0x5104, 2);
+        m.cpu_store(0x5c10, 0x77);
+        assert_eq!(m.cpu_load(0x5c10), Some(0x77));
+    }
+
+    #[test]
+    fn scanline_irq() {
+        let mut m = Mmc5::new(cart());
+        m.cpu_bus(0x2001, 0x18);
+        m.cpu_store(0x5203, 10);
+        m.cpu_store(0x5204, 0x80);
+        for line in 0..=10 {
+            assert!(!m.irq());
+            m.ppu_tick(line, 2);
+        }
+        assert!(m.irq());
+        assert_eq!(m.cpu_load(0x5204), Some(0xc0));
+        assert!(!m.irq());
+        m.ppu_tick(240, 2);
+        assert_eq!(m.cpu_load(0x5204), Some(0x00));
+    }
+
+    #[test]
+    fn pulse_audio() {
+        let mut m = Mmc5::new(cart());
+        assert_eq!(m.audio(), 0.0);
+        m.cpu_store(0x5015, 0x01);
+        m.cpu_store(0x5000, 0xbf);
+        m.cpu_store(0x5002, 0x10);
+        m.cpu_store(0x5003, 0x08);
+        let heard = (0..200).any(|_| {
+            m.cpu_tick();
+            m.audio() > 0.0
+        });
+        assert!(heard);
+        assert_eq!(m.cpu_load(0x5015), Some(0x01));
+    }
+}
@@ -0,0 +1,183 @@
+//! Mappers.
+//! The cartridge connector carries the cpu's address and data lines from $4020 up, the whole of the ppu's pattern
+//! table space, the nametable chip selects and the cpu's IRQ line. What the board does with them is what the iNES
+//! mapper number names: the simplest boards wire rom straight to the pins, later ones put bank switching latches,
+//! scanline counters and even extra sound chips in between.
+//! [Mapper] is that connector. The bus hands it cpu accesses in $4020-$FFFF, the ppu hands it pattern table accesses
+//! and asks it how the nametables are mirrored, and both tell it the time so it can count cycles and scanlines.
+use super::cartridge::{Cartridge, CartridgeError, Mirroring};
+use crate::bus::bank::Banks;
+
+mod discrete;
+mod mmc1;
+mod mmc2;
+mod mmc3;
+mod mmc5;
+mod nrom;
+mod vrc;
+mod vrc6;
+
+pub use discrete::{Board, Discrete};
+pub use mmc1::Mmc1;
+pub use mmc2::{Chip, Mmc2};
+pub use mmc3::{Mmc3, Revision};
+pub use mmc5::Mmc5;
+pub use nrom::Nrom;
+pub use vrc::Vrc;
+pub use vrc6::Vrc6;
+
+pub trait Mapper {
+    /// a cpu load in $4020-$FFFF. `None` if nothing on the board drives the bus at `addr`
+    fn cpu_load(&mut self, addr: u16) -> Option<u8>;
+
+    /// a cpu store in $4020-$FFFF
+    fn cpu_store(&mut self, addr: u16, v: u8);
+
+    /// a ppu load in the pattern tables, $0000-$1FFF. Every tile fetch comes through here, so boards that switch on
+    /// what the ppu fetches (the MMC2's $FD/$FE latches) can snoop it
+    fn ppu_load(&mut self, addr: u16) -> u8;
+
+    /// a ppu store in the pattern tables, $0000-$1FFF. Only does anything on boards with CHR-RAM
+    fn ppu_store(&mut self, addr: u16, v: u8);
+
+    /// every address the ppu drives onto its bus, pattern table, nametable and palette fetches alike, before the
+    /// access itself. For boards that watch the ppu's address lines, like the MMC3 counting A12 edges
+    fn ppu_bus(&mut self, _addr: u16) {}
+
+    /// how the nametables are currently mirrored
+    fn mirroring(&self) -> Mirroring;
+
+    /// which 1KB page of the console's nametable ram answers for nametable `nt` (0-3). Boards that can map each
+    /// nametable on its own, which [Mirroring] cannot describe, override this
+    fn ciram_page(&self, nt: u16) -> u16 {
+        self.mirroring().page(nt)
+    }
+
+    /// a ppu load in the nametables, $2000-$2FFF. Boards that put their own memory there (the MMC5's ExRAM and fill
+    /// mode) answer it; `None` leaves it to the console's nametable ram, as [Self::ciram_page] says
+    fn nt_load(&mut self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    /// a ppu store in the nametables. `true` if the board took it, so the console's ram should not
+    fn nt_store(&mut self, _addr: u16, _v: u8) -> bool {
+        false
+    }
+
+    /// every cpu store, wherever it goes. The cartridge connector carries the whole address bus, and a few boards
+    /// decode more than their own range: the MMC5 watches the writes to the ppu's $2000 and $2001
+    fn cpu_bus(&mut self, _addr: u16, _v: u8) {}
+
+    /// the level of the board's IRQ output. The cpu's IRQ line is the wired-or of this and the apu's
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// the board's expansion audio, if it has a sound chip. A level on the same scale as the apu's mixed output,
+    /// which it gets added to
+    fn audio(&self) -> f32 {
+        0.0
+    }
+
+    /// called once per cpu cycle
+    fn cpu_tick(&mut self) {}
+
+    /// called once per ppu dot, with the dot's position in the frame
+    fn ppu_tick(&mut self, _scanline: u16, _dot: u16) {}
+
+    /// the battery-backed ram, if the board has any, so the host can save and restore it
+    fn save_ram(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+}
+
+/// The empty cartridge slot. Nothing answers on either bus
+pub(crate) struct Unplugged;
+
+impl Mapper for Unplugged {
+    fn cpu_load(&mut self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    fn cpu_store(&mut self, _addr: u16, _v: u8) {}
+
+    fn ppu_load(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn ppu_store(&mut self, _addr: u16, _v: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+}
+
+/// builds the mapper the cartridge's header asks for, loaded with the cartridge's memories
+pub fn from_cartridge(cart: Cartridge) -> Result<Box<dyn Mapper>, CartridgeError> {
+    match cart.header.mapper {
+        0 => Ok(Box::new(Nrom::new(cart))),
+        1 => Ok(Box::new(Mmc1::new(cart))),
+        4 => Ok(Box::new(Mmc3::new(cart))),
+        5 => Ok(Box::new(Mmc5::new(cart))),
+        9 => Ok(Box::new(Mmc2::new(Chip::Mmc2, cart))),
+        10 => Ok(Box::new(Mmc2::new(Chip::Mmc4, cart))),
+        21 | 22 | 23 | 25 => Ok(Box::new(Vrc::new(cart))),
+        24 | 26 => Ok(Box::new(Vrc6::new(cart))),
+        n => match Board::from_mapper(n) {
+            Some(board) => Ok(Box::new(Discrete::new(board, cart))),
+            None => Err(CartridgeError::UnsupportedMapper(n)),
+        },
+    }
+}
+
+/// the pattern table memory: the CHR-ROM if the cartridge has one, otherwise as much CHR-RAM as the header asks for
+/// (but at least 8KB, which is what every CHR-RAM board carries)
+pub(super) fn chr(cart: &Cartridge, window: usize, windows: usize) -> Banks {
+    if cart.chr_rom.is_empty() {
+        let size = (cart.header.chr_ram_size + cart.header.chr_nvram_size).max(0x2000);
+        Banks::ram(size, window, windows)
+    } else {
+        Banks::new(cart.chr_rom.clone(), window, windows)
+    }
+}
+
+/// a cartridge for tests: NES 2.0, with `prg_size` bytes of PRG-ROM and `chr_size` bytes of CHR-ROM (none for CHR-RAM),
+/// every `prg_bank` and `chr_bank` bytes of them filled with the number of the bank, and 8KB of PRG-RAM. Mirroring is
+/// horizontal; tests that need something else change the header
+#[cfg(test)]
+pub(crate) fn test_cart(
+    mapper: u16,
+    submapper: u8,
+    prg_size: usize,
+    prg_bank: usize,
+    chr_size: usize,
+    chr_bank: usize,
+) -> Cartridge {
+    let mut b = vec![
+        b'N',
+        b'E',
+        b'S',
+        0x1a,
+        (prg_size / 0x4000) as u8,
+        (chr_size / 0x2000) as u8,
+        (mapper << 4) as u8,
+        (mapper & 0xf0) as u8 | 0x08,
+        (mapper >> 8) as u8 | submapper << 4,
+        0,
+        // 64 << 7 bytes of PRG-RAM
+        0x07,
+    ];
+    b.resize(16, 0);
+    b.extend((0..prg_size).map(|i| (i / prg_bank) as u8));
+    b.extend((0..chr_size).map(|i| (i / chr_bank) as u8));
+    Cartridge::from_bytes(&b).unwrap()
+}
+
+/// the PRG-RAM at $6000-$7FFF, if the board has any, as one 8KB window
+pub(super) fn prg_ram(cart: &Cartridge) -> Option<Banks> {
+    if cart.prg_ram.is_empty() {
+        None
+    } else {
+        Some(Banks::new(cart.prg_ram.clone(), 0x2000, 1).writable())
+    }
+}
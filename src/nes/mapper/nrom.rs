@@ -0,0 +1,102 @@
+//! NROM, mapper 0. No mapper at all: 16KB or 32KB of PRG-ROM and 8KB of CHR-ROM wired straight to the connector.
+//! NROM-128 boards only have 16KB, and with A14 unconnected it shows up twice, at $8000 and at $C000.
+//! The board has no PRG-RAM; iNES headers claim 8KB anyway and Family BASIC really has it, so we provide it if asked.
+//! Some homebrew puts CHR-RAM in place of the CHR-ROM.
+//! Donkey Kong, Super Mario Bros, Balloon Fight and Excitebike all run on it.
+use super::{chr, prg_ram, Mapper};
+use crate::bus::bank::Banks;
+use crate::nes::cartridge::{Cartridge, Mirroring};
+
+pub struct Nrom {
+    prg: Banks,
+    chr: Banks,
+    ram: Option<Banks>,
+    mirroring: Mirroring,
+    battery: bool,
+}
+
+impl Nrom {
+    pub fn new(cart: Cartridge) -> Self {
+        let mut prg = Banks::new(cart.prg_rom.clone(), 0x4000, 2);
+        // the last 16KB at $C000. on NROM-128 that is the only 16KB there is, so it mirrors $8000
+        prg.switch(1, -1);
+        Self {
+            prg,
+            chr: chr(&cart, 0x2000, 1),
+            ram: prg_ram(&cart),
+            mirroring: cart.header.mirroring,
+            battery: cart.header.battery,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_load(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff => self.ram.as_ref().map(|r| r.read(addr as usize - 0x6000)),
+            0x8000..=0xffff => Some(self.prg.read(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_store(&mut self, addr: u16, v: u8) {
+        if let (0x6000..=0x7fff, Some(r)) = (addr, self.ram.as_mut()) {
+            r.write(addr as usize - 0x6000, v);
+        }
+    }
+
+    fn ppu_load(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize & 0x1fff)
+    }
+
+    fn ppu_store(&mut self, addr: u16, v: u8) {
+        self.chr.write(addr as usize & 0x1fff, v);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_ram(&mut self) -> Option<&mut [u8]> {
+        match (self.battery, self.ram.as_mut()) {
+            (true, Some(r)) => Some(r.data_mut()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::mapper::test_cart;
+
+    fn cart(prg_banks: usize, chr_banks: usize) -> Cartridge {
+        let mut c = test_cart(0, 0, prg_banks * 0x4000, 0x4000, chr_banks * 0x2000, 0x2000);
+        c.header.mirroring = Mirroring::Vertical;
+        c
+    }
+
+    #[test]
+    fn nrom_128_mirrors_prg() {
+        let mut m = Nrom::new(cart(1, 1));
+        assert_eq!(m.cpu_load(0x8000), Some(0));
+        assert_eq!(m.cpu_load(0xc000), Some(0));
+        assert_eq!(m.mirroring(), Mirroring::Vertical);
+        // chr rom ignores writes
+        m.ppu_store(0x0010, 0x12);
+        assert_eq!(m.ppu_load(0x0010), 0);
+    }
+
+    #[test]
+    fn nrom_256_with_chr_ram() {
+        let mut m = Nrom::new(cart(2, 0));
+        assert_eq!(m.cpu_load(0xbfff), Some(0));
+        assert_eq!(m.cpu_load(0xc000), Some(1));
+        m.ppu_store(0x1fff, 0x34);
+        assert_eq!(m.ppu_load(0x1fff), 0x34);
+        // ram the ines header implies
+        m.cpu_store(0x6000, 0x56);
+        assert_eq!(m.cpu_load(0x6000), Some(0x56));
+        assert_eq!(m.cpu_load(0x5000), None);
+    }
+}
@@ -0,0 +1,299 @@
+//! Konami's VRC2 and VRC4, mappers 21, 22, 23 and 25: Gradius II, Contra (Japanese), Ganbare Goemon 2, Parodius.
+//! Two switchable 8KB PRG banks, eight 1KB CHR banks loaded a nibble at a time, and on the VRC4 the VRC IRQ counter.
+//! Each register sits at one of four addresses in its 4KB block, picked by two address lines. Which two depends on
+//! the board, and that is what the mapper numbers (and NES 2.0 submappers) tell apart:
+//!
+//! | mapper | submapper | chip  | register select lines |
+//! |--------|-----------|-------|-----------------------|
+//! | 21     | 1         | VRC4a | A1, A2                |
+//! | 21     | 2         | VRC4c | A6, A7                |
+//! | 22     |           | VRC2a | A1, A0 (CHR banks in 2KB units) |
+//! | 23     | 1         | VRC4f | A0, A1                |
+//! | 23     | 2         | VRC4e | A2, A3                |
+//! | 23     | 3         | VRC2b | A0, A1                |
+//! | 25     | 1         | VRC4b | A1, A0                |
+//! | 25     | 2         | VRC4d | A3, A2                |
+//! | 25     | 3         | VRC2c | A1, A0                |
+//!
+//! An iNES header cannot say which board it is, so without a submapper both candidate wirings are ORed together;
+//! games only write to one of the two sets of addresses, so that finds the right register either way.
+//! Mapper 24 and 26 are the VRC6, in [super::vrc6].
+//! [reference](https://www.nesdev.org/wiki/VRC2_and_VRC4)
+use super::{chr, prg_ram, Mapper};
+use crate::bus::bank::Banks;
+use crate::nes::cartridge::{Cartridge, Mirroring};
+
+/// The IRQ counter of the VRC4, VRC6 and VRC7. An 8 bit counter that counts up to $FF and then reloads from a latch
+/// and raises the IRQ. In cycle mode it counts every cpu cycle; in scanline mode a prescaler divides the cpu clock by
+/// 113.667 (341 ppu dots over 3), so it counts roughly once per scanline without watching the ppu at all
+#[derive(Debug, Default)]
+pub(super) struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    // the enable to restore on acknowledge, the current enable, cycle mode
+    enable_after_ack: bool,
+    enabled: bool,
+    cycle_mode: bool,
+    line: bool,
+}
+
+impl VrcIrq {
+    pub(super) fn write_latch_lo(&mut self, v: u8) {
+        self.latch = (self.latch & 0xf0) | (v & 0x0f);
+    }
+
+    pub(super) fn write_latch_hi(&mut self, v: u8) {
+        self.latch = (self.latch & 0x0f) | (v << 4);
+    }
+
+    pub(super) fn write_latch(&mut self, v: u8) {
+        self.latch = v;
+    }
+
+    pub(super) fn write_control(&mut self, v: u8) {
+        self.enable_after_ack = v & 0x01 != 0;
+        self.enabled = v & 0x02 != 0;
+        self.cycle_mode = v & 0x04 != 0;
+        self.line = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+    }
+
+    pub(super) fn acknowledge(&mut self) {
+        self.line = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    pub(super) fn line(&self) -> bool {
+        self.line
+    }
+
+    fn clock(&mut self) {
+        if self.counter == 0xff {
+            self.counter = self.latch;
+            self.line = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+
+    /// once per cpu cycle
+    pub(super) fn tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += 341;
+                self.clock();
+            }
+        }
+    }
+}
+
+pub struct Vrc {
+    prg: Banks,
+    chr: Banks,
+    ram: Option<Banks>,
+    battery: bool,
+    vrc4: bool,
+    // the address bits that drive register select lines 0 and 1
+    wiring: (u16, u16),
+    // VRC2a ignores the low bit of its CHR banks
+    chr_shift: u8,
+
+    prg_regs: [u8; 2],
+    swap: bool,
+    chr_regs: [u16; 8],
+    mirroring: Mirroring,
+    irq: VrcIrq,
+}
+
+impl Vrc {
+    pub fn new(cart: Cartridge) -> Self {
+        let (a0, a1, a2, a3, a6, a7) = (0x01, 0x02, 0x04, 0x08, 0x40, 0x80);
+        let h = &cart.header;
+        let (vrc4, wiring) = match (h.mapper, h.submapper) {
+            (21, 1) => (true, (a1, a2)),
+            (21, 2) => (true, (a6, a7)),
+            (21, _) => (true, (a1 | a6, a2 | a7)),
+            (22, _) => (false, (a1, a0)),
+            (23, 1) => (true, (a0, a1)),
+            (23, 2) => (true, (a2, a3)),
+            (23, 3) => (false, (a0, a1)),
+            (23, _) => (true, (a0 | a2, a1 | a3)),
+            (25, 1) => (true, (a1, a0)),
+            (25, 2) => (true, (a3, a2)),
+            (25, 3) => (false, (a1, a0)),
+            _ => (true, (a1 | a3, a0 | a2)),
+        };
+        let mut m = Self {
+            prg: Banks::new(cart.prg_rom.clone(), 0x2000, 4),
+            chr: chr(&cart, 0x0400, 8),
+            ram: prg_ram(&cart),
+            battery: h.battery,
+            vrc4,
+            wiring,
+            chr_shift: (h.mapper == 22) as u8,
+            prg_regs: [0, 1],
+            swap: false,
+            chr_regs: [0; 8],
+            mirroring: h.mirroring,
+            irq: VrcIrq::default(),
+        };
+        m.update();
+        m
+    }
+
+    fn update(&mut self) {
+        let (p0, p1) = (self.prg_regs[0] as isize, self.prg_regs[1] as isize);
+        let (lo, hi) = if self.swap { (-2, p0) } else { (p0, -2) };
+        self.prg.switch(0, lo);
+        self.prg.switch(1, p1);
+        self.prg.switch(2, hi);
+        self.prg.switch(3, -1);
+        for (w, r) in self.chr_regs.into_iter().enumerate() {
+            self.chr.switch(w, (r >> self.chr_shift) as isize);
+        }
+    }
+
+    // the register number (0-3) within the 4KB block that `addr` selects
+    fn reg(&self, addr: u16) -> u16 {
+        (addr & self.wiring.0 != 0) as u16 | ((addr & self.wiring.1 != 0) as u16) << 1
+    }
+}
+
+impl Mapper for Vrc {
+    fn cpu_load(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff => self.ram.as_ref().map(|r| r.read(addr as usize - 0x6000)),
+            0x8000..=0xffff => Some(self.prg.read(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_store(&mut self, addr: u16, v: u8) {
+        let reg = self.reg(addr);
+        match (addr & 0xf000, reg) {
+            (0x6000 | 0x7000, _) => {
+                if let Some(r) = self.ram.as_mut() {
+                    r.write(addr as usize - 0x6000, v);
+                }
+            }
+            (0x8000, _) => self.prg_regs[0] = v & 0x1f,
+            (0x9000, 0 | 1) if self.vrc4 => {
+                self.mirroring = match v & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleLower,
+                    _ => Mirroring::SingleUpper,
+                }
+            }
+            (0x9000, _) if self.vrc4 => self.swap = v & 0x02 != 0,
+            (0x9000, _) => {
+                self.mirroring = if v & 0x01 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                }
+            }
+            (0xa000, _) => self.prg_regs[1] = v & 0x1f,
+            (0xb000..=0xe000, _) => {
+                // two banks per block, low nibble at the even register and the high bits at the odd one
+                let bank = ((addr - 0xb000) >> 12) as usize * 2 + (reg >> 1) as usize;
+                let r = &mut self.chr_regs[bank];
+                *r = if reg & 1 == 0 {
+                    (*r & 0x1f0) | (v as u16 & 0x0f)
+                } else {
+                    (*r & 0x0f) | ((v as u16 & 0x1f) << 4)
+                };
+            }
+            (0xf000, 0) if self.vrc4 => self.irq.write_latch_lo(v),
+            (0xf000, 1) if self.vrc4 => self.irq.write_latch_hi(v),
+            (0xf000, 2) if self.vrc4 => self.irq.write_control(v),
+            (0xf000, 3) if self.vrc4 => self.irq.acknowledge(),
+            _ => return,
+        }
+        self.update();
+    }
+
+    fn ppu_load(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize & 0x1fff)
+    }
+
+    fn ppu_store(&mut self, addr: u16, v: u8) {
+        self.chr.write(addr as usize & 0x1fff, v);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.line()
+    }
+
+    fn cpu_tick(&mut self) {
+        self.irq.tick();
+    }
+
+    fn save_ram(&mut self) -> Option<&mut [u8]> {
+        match (self.battery, self.ram.as_mut()) {
+            (true, Some(r)) => Some(r.data_mut()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::mapper::test_cart;
+
+    // 8KB prg banks and 1KB chr banks tagged with their number
+    fn cart(mapper: u16, submapper: u8) -> Cartridge {
+        test_cart(mapper, submapper, 0x20000, 0x2000, 0x20000, 0x0400)
+    }
+
+    #[test]
+    fn wirings() {
+        // the same register, CHR bank 1 high nibble, at each board's address
+        for (mapper, sub, addr) in [(21, 1, 0xb006), (21, 2, 0xb0c0), (23, 2, 0xb00c), (25, 1, 0xb003), (25, 0, 0xb00c)] {
+            let mut m = Vrc::new(cart(mapper, sub));
+            m.cpu_store(addr, 0x04);
+            assert_eq!(m.ppu_load(0x0400), 0x40, "mapper {} submapper {}", mapper, sub);
+        }
+        // VRC2a's banks are in 2KB units
+        let mut m = Vrc::new(cart(22, 0));
+        m.cpu_store(0xb000, 0x06);
+        assert_eq!(m.ppu_load(0x0000), 3);
+    }
+
+    #[test]
+    fn prg_swap_and_cycle_irq() {
+        let mut m = Vrc::new(cart(23, 1));
+        m.cpu_store(0x8000, 3);
+        assert_eq!((m.cpu_load(0x8000), m.cpu_load(0xc000)), (Some(3), Some(14)));
+        m.cpu_store(0x9002, 0x02);
+        assert_eq!((m.cpu_load(0x8000), m.cpu_load(0xc000)), (Some(14), Some(3)));
+
+        m.cpu_store(0xf000, 0x0d);
+        m.cpu_store(0xf001, 0x0f);
+        m.cpu_store(0xf002, 0x07);
+        // $FE, $FF, then the overflow
+        for _ in 0..2 {
+            m.cpu_tick();
+            assert!(!m.irq());
+        }
+        m.cpu_tick();
+        assert!(m.irq());
+        m.cpu_store(0xf003, 0);
+        assert!(!m.irq());
+    }
+}
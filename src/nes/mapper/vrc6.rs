@@ -0,0 +1,325 @@
+//! Konami's VRC6, mappers 24 (VRC6a: Akumajou Densetsu) and 26 (VRC6b: Madara, Esper Dream 2). The two only differ in
+//! which of A0 and A1 selects the register within each 4KB block.
+//! A 16KB and an 8KB switchable PRG bank, eight 1KB CHR banks, the VRC IRQ counter, and a sound chip: two pulse
+//! channels with 8 duty settings and a sawtooth, which is where the Famicom version of Castlevania III gets its
+//! soundtrack from.
+//!
+//! | register      |                                                                                   |
+//! |---------------|-----------------------------------------------------------------------------------|
+//! | $8000-$8003   | 16KB PRG bank at $8000                                                              |
+//! | $9000-$9002   | pulse 1: `MDDDVVVV` mode, duty, volume; period low; `E...PPPP` enable, period high   |
+//! | $9003         | `.....ABH` halt all, periods divided by 16 (A) or 256 (B)                           |
+//! | $A000-$A002   | pulse 2                                                                             |
+//! | $B000-$B002   | saw: `..RRRRRR` accumulator rate; period low; enable, period high                   |
+//! | $B003         | `R...MMCC` PRG-RAM enable, mirroring, CHR banking mode                                |
+//! | $C000-$C003   | 8KB PRG bank at $C000                                                               |
+//! | $D000-$E003   | CHR banks 0-7                                                                       |
+//! | $F000-$F002   | IRQ latch, control, acknowledge                                                     |
+//!
+//! [reference](https://www.nesdev.org/wiki/VRC6) and [audio](https://www.nesdev.org/wiki/VRC6_audio)
+use super::vrc::VrcIrq;
+use super::{chr, prg_ram, Mapper};
+use crate::bus::bank::Banks;
+use crate::nes::cartridge::{Cartridge, Mirroring};
+
+// one step of the VRC6's 0-61 output, on the scale of the apu's mix. The chip's pulses are about as loud as the apu's
+const LEVEL: f32 = 0.00862;
+
+#[derive(Debug, Default)]
+struct Pulse {
+    volume: u8,
+    duty: u8,
+    // ignore the duty, output the volume all the time
+    digitized: bool,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+}
+
+impl Pulse {
+    fn write(&mut self, reg: u16, v: u8) {
+        match reg {
+            0 => {
+                self.digitized = v & 0x80 != 0;
+                self.duty = (v >> 4) & 0x07;
+                self.volume = v & 0x0f;
+            }
+            1 => self.period = (self.period & 0xf00) | v as u16,
+            _ => {
+                self.period = (self.period & 0xff) | ((v as u16 & 0x0f) << 8);
+                self.enabled = v & 0x80 != 0;
+                // disabling resets the duty cycle
+                if !self.enabled {
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = (self.step + 1) & 0x0f;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.digitized || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Saw {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+    acc: u8,
+}
+
+impl Saw {
+    fn write(&mut self, reg: u16, v: u8) {
+        match reg {
+            0 => self.rate = v & 0x3f,
+            1 => self.period = (self.period & 0xf00) | v as u16,
+            _ => {
+                self.period = (self.period & 0xff) | ((v as u16 & 0x0f) << 8);
+                self.enabled = v & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.acc = 0;
+                }
+            }
+        }
+    }
+
+    // the accumulator takes the rate on every second step, and clears after the 14th
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> shift;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.acc = 0;
+        } else if self.step & 1 == 0 {
+            self.acc = self.acc.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.acc >> 3
+    }
+}
+
+pub struct Vrc6 {
+    prg: Banks,
+    chr: Banks,
+    ram: Option<Banks>,
+    battery: bool,
+    // VRC6b swaps the register select lines
+    swapped: bool,
+
+    prg_regs: [u8; 2],
+    chr_regs: [u8; 8],
+    control: u8,
+    irq: VrcIrq,
+
+    pulses: [Pulse; 2],
+    saw: Saw,
+    halt: bool,
+    shift: u8,
+}
+
+impl Vrc6 {
+    pub fn new(cart: Cartridge) -> Self {
+        let mut m = Self {
+            prg: Banks::new(cart.prg_rom.clone(), 0x2000, 4),
+            chr: chr(&cart, 0x0400, 8),
+            ram: prg_ram(&cart),
+            battery: cart.header.battery,
+            swapped: cart.header.mapper == 26,
+            prg_regs: [0; 2],
+            chr_regs: [0; 8],
+            control: 0,
+            irq: VrcIrq::default(),
+            pulses: Default::default(),
+            saw: Saw::default(),
+            halt: false,
+            shift: 0,
+        };
+        m.update();
+        m
+    }
+
+    fn update(&mut self) {
+        let p = self.prg_regs[0] as isize;
+        self.prg.switch(0, p * 2);
+        self.prg.switch(1, p * 2 + 1);
+        self.prg.switch(2, self.prg_regs[1] as isize);
+        self.prg.switch(3, -1);
+
+        let regs = self.chr_regs;
+        let r = |i: usize| regs[i] as isize;
+        // mode 0: eight 1KB banks. mode 1: four 2KB banks. modes 2 and 3: 1KB banks below $1000, 2KB banks above
+        let banks: [isize; 8] = match self.control & 0x03 {
+            0 => [r(0), r(1), r(2), r(3), r(4), r(5), r(6), r(7)],
+            1 => [r(0) * 2, r(0) * 2 + 1, r(1) * 2, r(1) * 2 + 1, r(2) * 2, r(2) * 2 + 1, r(3) * 2, r(3) * 2 + 1],
+            _ => [r(0), r(1), r(2), r(3), r(4) * 2, r(4) * 2 + 1, r(5) * 2, r(5) * 2 + 1],
+        };
+        for (w, b) in banks.into_iter().enumerate() {
+            self.chr.switch(w, b);
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.control & 0x80 != 0
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_load(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff if self.ram_enabled() => self.ram.as_ref().map(|r| r.read(addr as usize - 0x6000)),
+            0x8000..=0xffff => Some(self.prg.read(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_store(&mut self, addr: u16, v: u8) {
+        let reg = if self.swapped {
+            (addr & 0x01) << 1 | (addr & 0x02) >> 1
+        } else {
+            addr & 0x03
+        };
+        match (addr & 0xf000, reg) {
+            (0x6000 | 0x7000, _) if self.ram_enabled() => {
+                if let Some(r) = self.ram.as_mut() {
+                    r.write(addr as usize - 0x6000, v);
+                }
+            }
+            (0x8000, _) => self.prg_regs[0] = v,
+            (0x9000, 3) => {
+                self.halt = v & 0x01 != 0;
+                self.shift = if v & 0x04 != 0 {
+                    8
+                } else if v & 0x02 != 0 {
+                    4
+                } else {
+                    0
+                };
+            }
+            (0x9000, _) => self.pulses[0].write(reg, v),
+            (0xa000, 3) => (),
+            (0xa000, _) => self.pulses[1].write(reg, v),
+            (0xb000, 3) => self.control = v,
+            (0xb000, _) => self.saw.write(reg, v),
+            (0xc000, _) => self.prg_regs[1] = v,
+            (0xd000, _) => self.chr_regs[reg as usize] = v,
+            (0xe000, _) => self.chr_regs[4 + reg as usize] = v,
+            (0xf000, 0) => self.irq.write_latch(v),
+            (0xf000, 1) => self.irq.write_control(v),
+            (0xf000, 2) => self.irq.acknowledge(),
+            _ => return,
+        }
+        self.update();
+    }
+
+    fn ppu_load(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize & 0x1fff)
+    }
+
+    fn ppu_store(&mut self, addr: u16, v: u8) {
+        self.chr.write(addr as usize & 0x1fff, v);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.control >> 2) & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleLower,
+            _ => Mirroring::SingleUpper,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.line()
+    }
+
+    fn audio(&self) -> f32 {
+        let out = self.pulses[0].output() + self.pulses[1].output() + self.saw.output();
+        out as f32 * LEVEL
+    }
+
+    fn cpu_tick(&mut self) {
+        self.irq.tick();
+        if !self.halt {
+            let shift = self.shift;
+            self.pulses.iter_mut().for_each(|p| p.clock(shift));
+            self.saw.clock(shift);
+        }
+    }
+
+    fn save_ram(&mut self) -> Option<&mut [u8]> {
+        match (self.battery, self.ram.as_mut()) {
+            (true, Some(r)) => Some(r.data_mut()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::mapper::test_cart;
+
+    // 8KB prg banks and 1KB chr banks tagged with their number
+    fn cart(mapper: u16) -> Cartridge {
+        test_cart(mapper, 0, 0x20000, 0x2000, 0x20000, 0x0400)
+    }
+
+    #[test]
+    fn banking_and_vrc6b_lines() {
+        let mut m = Vrc6::new(cart(26));
+        m.cpu_store(0x8000, 2);
+        m.cpu_store(0xc000, 9);
+        assert_eq!((m.cpu_load(0x8000), m.cpu_load(0xa000), m.cpu_load(0xc000)), (Some(4), Some(5), Some(9)));
+        // $D002 on the VRC6b is CHR bank 1
+        m.cpu_store(0xd002, 33);
+        assert_eq!(m.ppu_load(0x0400), 33);
+        m.cpu_store(0xb003, 0x84);
+        assert_eq!(m.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn saw_ramps() {
+        let mut m = Vrc6::new(cart(24));
+        m.cpu_store(0xb000, 0x08);
+        m.cpu_store(0xb001, 0x00);
+        m.cpu_store(0xb002, 0x80);
+        let levels: Vec<u8> = (0..14)
+            .map(|_| {
+                m.cpu_tick();
+                m.saw.output()
+            })
+            .collect();
+        assert_eq!(levels, [0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 0]);
+        assert!(m.audio() == 0.0);
+    }
+}
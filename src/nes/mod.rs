@@ -0,0 +1,14 @@
+//! nes puts the [Six502](crate::six502::six502::Six502) in the Nintendo Entertainment System: the 2A03's memory map,
+//! the cartridge and its mapper, and the devices hanging off the bus.
+//! Best resource for all of it is the [nesdev wiki](https://www.nesdev.org/wiki/Nesdev_Wiki)
+pub(crate) mod apu;
+pub(crate) mod audio;
+pub(crate) mod cartridge;
+pub(crate) mod controller;
+pub(crate) mod mapper;
+pub(crate) mod nsf;
+pub(crate) mod ntsc;
+pub(crate) mod palette;
+pub(crate) mod ppu;
+pub(crate) mod region;
+pub(crate) mod video;
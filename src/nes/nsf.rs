@@ -0,0 +1,660 @@
+//! NSF music files, and a player for them.
+//! An NSF is the sound driver and music data ripped out of a game, with a header saying where to load it and which
+//! two routines to call: INIT once with the song number in A, then PLAY at a steady rate, normally once a frame. There
+//! is no ppu; the player stands in for the game's NMI handler. That makes an NSF the quickest way to hear the apu (and
+//! the sound chips some boards carry) on its own, and to check a change to it has not broken how something sounds.
+//!
+//! | offset | NSF header                                                                                   |
+//! |--------|----------------------------------------------------------------------------------------------|
+//! | $00    | `NESM\x1A`, then the version                                                                 |
+//! | $06    | songs, and the one to start on (from 1)                                                      |
+//! | $08    | load, INIT and PLAY addresses                                                                |
+//! | $0E    | title, artist and copyright, 32 bytes each                                                   |
+//! | $6E    | the NTSC PLAY period in µs, the initial banks, the PAL period                                 |
+//! | $7A    | `......DP` dual region, PAL; then the sound chips                                            |
+//! | $80    | the data                                                                                     |
+//!
+//! If any initial bank is not 0 the tune is bank switched: the data, after `load & $FFF` bytes of padding, is split
+//! into 4KB banks, and the eight 4KB windows of $8000-$FFFF are set by writes to $5FF8-$5FFF. Otherwise it is just
+//! copied in at the load address. Either way $6000-$7FFF is ram.
+//! NSFe carries the same in chunks (`INFO`, `DATA`, `BANK`, `RATE`, `auth`, `tlbl`, `time`), with names and lengths
+//! for each track. A chunk whose name starts with a capital letter has to be understood to play the file; the rest
+//! can be skipped.
+//! Of the sound chips, the VRC6's and the MMC5's are emulated, by the boards' own code; tunes for the others play
+//! without them.
+//! [reference](https://www.nesdev.org/wiki/NSF) and [NSFe](https://www.nesdev.org/wiki/NSFe)
+use super::apu::Apu;
+use super::audio::{AudioOut, AudioSink};
+use super::cartridge::{Cartridge, Console, Format, Header, Mirroring, Timing};
+use super::mapper::{Mapper, Mmc5, Vrc6};
+use super::region::Region;
+use crate::bus::bank::Banks;
+use crate::bus::{BusAccess, DataBus};
+use crate::six502::six502::Six502;
+use crate::wav::WavWriter;
+use crate::Cpu;
+use std::cell::RefCell;
+use std::io::{Seek, Write};
+use std::rc::Rc;
+use std::{error::Error, fmt, fs, path::Path};
+
+const MAGIC: [u8; 5] = *b"NESM\x1a";
+const MAGIC_E: [u8; 4] = *b"NSFE";
+const HEADER_LEN: usize = 0x80;
+// where INIT and PLAY return to. Nothing is there: the player stops the cpu before it gets to run it
+const RETURN: u16 = 0x4100;
+// INIT gets this long to return, in cpu cycles (about a second), before it is given up on and the tune plays anyway
+const INIT_LIMIT: u64 = 1 << 21;
+
+/// The sound chips a tune can ask for, as bits of [Nsf::chips]
+pub mod chips {
+    pub const VRC6: u8 = 0x01;
+    pub const VRC7: u8 = 0x02;
+    pub const FDS: u8 = 0x04;
+    pub const MMC5: u8 = 0x08;
+    pub const N163: u8 = 0x10;
+    pub const S5B: u8 = 0x20;
+    /// the ones the player has
+    pub const EMULATED: u8 = VRC6 | MMC5;
+}
+
+#[derive(Debug)]
+pub enum NsfError {
+    /// neither `NESM<EOF>` nor `NSFE`
+    BadMagic([u8; 4]),
+    /// the file ends inside the header or a chunk
+    Truncated {
+        section: &'static str,
+        want: usize,
+        have: usize,
+    },
+    /// an NSFe without one of the chunks every file has
+    MissingChunk(&'static str),
+    /// an NSFe chunk that has to be understood, and is not
+    UnknownChunk([u8; 4]),
+}
+
+impl fmt::Display for NsfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NsfError::BadMagic(m) => write!(f, "not an NSF or NSFe file: starts with {:02X?}", m),
+            NsfError::Truncated { section, want, have } => write!(
+                f,
+                "file is truncated: {} needs {} bytes but only {} are left",
+                section, want, have
+            ),
+            NsfError::MissingChunk(c) => write!(f, "NSFe file has no {} chunk", c),
+            NsfError::UnknownChunk(c) => {
+                write!(f, "NSFe chunk {:?} is required but not supported", String::from_utf8_lossy(c))
+            }
+        }
+    }
+}
+
+impl Error for NsfError {}
+
+/// An NSF or NSFe file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nsf {
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub songs: u8,
+    /// the song to play first, from 0
+    pub start: u8,
+    pub load: u16,
+    pub init: u16,
+    pub play: u16,
+    /// the initial bank of each window of $8000-$FFFF, for bank switched tunes
+    pub banks: Option<[u8; 8]>,
+    /// how often to call PLAY, in µs
+    pub ntsc_period: u16,
+    pub pal_period: u16,
+    /// the console the tune was made for. Tunes that run on either get NTSC
+    pub region: Region,
+    /// the sound chips the tune uses, see [chips]
+    pub chips: u8,
+    pub data: Vec<u8>,
+    /// NSFe only: the name of each track, and how long it lasts in ms
+    pub track_names: Vec<String>,
+    pub track_lengths: Vec<Option<u32>>,
+}
+
+impl Default for Nsf {
+    fn default() -> Self {
+        Self {
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            songs: 1,
+            start: 0,
+            load: 0x8000,
+            init: 0x8000,
+            play: 0x8000,
+            banks: None,
+            ntsc_period: 16639,
+            pal_period: 19997,
+            region: Region::Ntsc,
+            chips: 0,
+            data: vec![],
+            track_names: vec![],
+            track_lengths: vec![],
+        }
+    }
+}
+
+// the bytes at `at`, or what is missing
+fn bytes<'a>(b: &'a [u8], at: usize, len: usize, section: &'static str) -> Result<&'a [u8], NsfError> {
+    b.get(at..at + len).ok_or(NsfError::Truncated {
+        section,
+        want: len,
+        have: b.len().saturating_sub(at),
+    })
+}
+
+fn word(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+// a string that ends at the first NUL, or the end
+fn text(b: &[u8]) -> String {
+    let end = b.iter().position(|&c| c == 0).unwrap_or(b.len());
+    String::from_utf8_lossy(&b[..end]).into_owned()
+}
+
+// bit 0 of the region byte is PAL, bit 1 either
+fn region(flags: u8) -> Region {
+    if flags & 0x03 == 0x01 {
+        Region::Pal
+    } else {
+        Region::Ntsc
+    }
+}
+
+fn banks(b: &[u8]) -> Option<[u8; 8]> {
+    let mut banks = [0; 8];
+    banks[..b.len().min(8)].copy_from_slice(&b[..b.len().min(8)]);
+    banks.iter().any(|&n| n != 0).then_some(banks)
+}
+
+impl Nsf {
+    pub fn open<T: AsRef<Path>>(path: T) -> Result<Self, Box<dyn Error>> {
+        let b = fs::read(path)?;
+        Ok(Self::from_bytes(&b)?)
+    }
+
+    pub fn from_bytes(b: &[u8]) -> Result<Self, NsfError> {
+        if b.starts_with(&MAGIC) {
+            Self::from_nsf(b)
+        } else if b.starts_with(&MAGIC_E) {
+            Self::from_nsfe(b)
+        } else {
+            let mut magic = [0; 4];
+            magic[..b.len().min(4)].copy_from_slice(&b[..b.len().min(4)]);
+            Err(NsfError::BadMagic(magic))
+        }
+    }
+
+    fn from_nsf(b: &[u8]) -> Result<Self, NsfError> {
+        let h = bytes(b, 0, HEADER_LEN, "header")?;
+        let rest = &b[HEADER_LEN..];
+        // NSF2 can say how long the data is, and put metadata after it
+        let len = u32::from_le_bytes([h[0x7d], h[0x7e], h[0x7f], 0]) as usize;
+        let data = if h[5] >= 2 && len != 0 {
+            bytes(rest, 0, len, "data")?
+        } else {
+            rest
+        };
+        Ok(Self {
+            title: text(&h[0x0e..0x2e]),
+            artist: text(&h[0x2e..0x4e]),
+            copyright: text(&h[0x4e..0x6e]),
+            songs: h[6],
+            start: h[7].saturating_sub(1),
+            load: word(h, 0x08),
+            init: word(h, 0x0a),
+            play: word(h, 0x0c),
+            banks: banks(&h[0x70..0x78]),
+            ntsc_period: word(h, 0x6e),
+            pal_period: word(h, 0x78),
+            region: region(h[0x7a]),
+            chips: h[0x7b],
+            data: data.to_vec(),
+            ..Default::default()
+        })
+    }
+
+    fn from_nsfe(b: &[u8]) -> Result<Self, NsfError> {
+        let mut nsf = Self::default();
+        let (mut info, mut data) = (false, false);
+        let mut at = MAGIC_E.len();
+        loop {
+            let head = bytes(b, at, 8, "chunk header")?;
+            let len = u32::from_le_bytes([head[0], head[1], head[2], head[3]]) as usize;
+            let id = [head[4], head[5], head[6], head[7]];
+            let c = bytes(b, at + 8, len, "chunk")?;
+            at += 8 + len;
+            match &id {
+                b"INFO" => {
+                    let h = bytes(c, 0, 8, "INFO")?;
+                    nsf.load = word(h, 0);
+                    nsf.init = word(h, 2);
+                    nsf.play = word(h, 4);
+                    nsf.region = region(h[6]);
+                    nsf.chips = h[7];
+                    // the rest is optional
+                    nsf.songs = c.get(8).copied().unwrap_or(1);
+                    nsf.start = c.get(9).copied().unwrap_or(0);
+                    info = true;
+                }
+                b"DATA" => {
+                    nsf.data = c.to_vec();
+                    data = true;
+                }
+                b"BANK" => nsf.banks = banks(c),
+                b"RATE" => {
+                    if c.len() >= 2 {
+                        nsf.ntsc_period = word(c, 0);
+                    }
+                    if c.len() >= 4 {
+                        nsf.pal_period = word(c, 2);
+                    }
+                }
+                b"auth" => {
+                    let mut s = c.split(|&x| x == 0).map(text);
+                    nsf.title = s.next().unwrap_or_default();
+                    nsf.artist = s.next().unwrap_or_default();
+                    nsf.copyright = s.next().unwrap_or_default();
+                }
+                b"tlbl" => {
+                    nsf.track_names = c.split(|&x| x == 0).map(text).take(nsf.songs as usize).collect();
+                }
+                b"time" => {
+                    nsf.track_lengths = c
+                        .chunks_exact(4)
+                        .map(|t| i32::from_le_bytes([t[0], t[1], t[2], t[3]]))
+                        .map(|ms| u32::try_from(ms).ok())
+                        .collect();
+                }
+                b"NEND" => break,
+                id if id[0].is_ascii_uppercase() => return Err(NsfError::UnknownChunk(*id)),
+                _ => (),
+            }
+        }
+        if !info {
+            return Err(NsfError::MissingChunk("INFO"));
+        }
+        if !data {
+            return Err(NsfError::MissingChunk("DATA"));
+        }
+        Ok(nsf)
+    }
+
+    /// how often PLAY is called in `region`, in µs
+    pub fn period(&self, region: Region) -> u16 {
+        let (p, default) = match region {
+            Region::Ntsc => (self.ntsc_period, 16639),
+            Region::Pal | Region::Dendy => (self.pal_period, 19997),
+        };
+        if p == 0 {
+            default
+        } else {
+            p
+        }
+    }
+}
+
+// a cartridge with nothing on it, to build the boards whose sound chips a tune uses
+fn blank(mapper: u16) -> Cartridge {
+    Cartridge {
+        header: Header {
+            format: Format::INes,
+            prg_rom_size: 0x4000,
+            chr_rom_size: 0,
+            mapper,
+            submapper: 0,
+            mirroring: Mirroring::Horizontal,
+            battery: false,
+            trainer: false,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0x2000,
+            chr_nvram_size: 0,
+            console: Console::Nes,
+            timing: Timing::Ntsc,
+        },
+        trainer: None,
+        prg_rom: vec![0; 0x4000],
+        chr_rom: vec![],
+        prg_ram: vec![],
+    }
+}
+
+/// The board an NSF player puts the tune on: the data in 4KB banks at $8000-$FFFF, 8KB of ram at $6000-$7FFF, and
+/// the registers of the sound chips the tune asks for
+pub struct NsfBoard {
+    prg: Banks,
+    switched: bool,
+    ram: Banks,
+    vrc6: Option<Vrc6>,
+    mmc5: Option<Mmc5>,
+}
+
+impl NsfBoard {
+    pub fn new(nsf: &Nsf) -> Self {
+        let (prg, switched) = match nsf.banks {
+            Some(initial) => {
+                let mut image = vec![0; nsf.load as usize & 0x0fff];
+                image.extend_from_slice(&nsf.data);
+                let mut prg = Banks::new(image, 0x1000, 8);
+                for (w, &b) in initial.iter().enumerate() {
+                    prg.switch(w, b as isize);
+                }
+                (prg, true)
+            }
+            None => {
+                let mut image = vec![0; 0x8000];
+                let at = (nsf.load as usize).saturating_sub(0x8000);
+                let n = nsf.data.len().min(0x8000 - at.min(0x8000));
+                image[at..at + n].copy_from_slice(&nsf.data[..n]);
+                let mut prg = Banks::new(image, 0x1000, 8);
+                for w in 0..8 {
+                    prg.switch(w, w as isize);
+                }
+                (prg, false)
+            }
+        };
+        let mmc5 = (nsf.chips & chips::MMC5 != 0).then(|| {
+            let mut m = Mmc5::new(blank(5));
+            // ExRAM as plain ram
+            m.cpu_store(0x5104, 2);
+            m
+        });
+        Self {
+            prg,
+            switched,
+            ram: Banks::ram(0x2000, 0x2000, 1),
+            vrc6: (nsf.chips & chips::VRC6 != 0).then(|| Vrc6::new(blank(24))),
+            mmc5,
+        }
+    }
+}
+
+impl Mapper for NsfBoard {
+    fn cpu_load(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5000..=0x5015 | 0x5205 | 0x5206 | 0x5c00..=0x5ff5 => self.mmc5.as_mut().and_then(|m| m.cpu_load(addr)),
+            0x6000..=0x7fff => Some(self.ram.read(addr as usize - 0x6000)),
+            0x8000..=0xffff => Some(self.prg.read(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_store(&mut self, addr: u16, v: u8) {
+        match addr {
+            0x5ff8..=0x5fff if self.switched => self.prg.switch(addr as usize - 0x5ff8, v as isize),
+            0x5000..=0x5015 | 0x5205 | 0x5206 | 0x5c00..=0x5ff5 => {
+                if let Some(m) = self.mmc5.as_mut() {
+                    m.cpu_store(addr, v);
+                }
+            }
+            0x6000..=0x7fff => self.ram.write(addr as usize - 0x6000, v),
+            0x9000..=0x9003 | 0xa000..=0xa002 | 0xb000..=0xb002 => {
+                if let Some(m) = self.vrc6.as_mut() {
+                    m.cpu_store(addr, v);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn ppu_load(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn ppu_store(&mut self, _addr: u16, _v: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+
+    fn audio(&self) -> f32 {
+        self.vrc6.as_ref().map_or(0.0, |m| m.audio()) + self.mmc5.as_ref().map_or(0.0, |m| m.audio())
+    }
+
+    fn cpu_tick(&mut self) {
+        if let Some(m) = self.vrc6.as_mut() {
+            m.cpu_tick();
+        }
+        if let Some(m) = self.mmc5.as_mut() {
+            m.cpu_tick();
+        }
+    }
+}
+
+/// Plays an [Nsf] on a cpu and apu with no ppu, into an [AudioOut]
+pub struct NsfPlayer {
+    nsf: Nsf,
+    cpu: Six502<DataBus>,
+    song: u8,
+    // cpu cycles between calls to PLAY, and when the next is due
+    period: f64,
+    next_play: f64,
+    // INIT has been called and is still to run
+    init: bool,
+}
+
+impl NsfPlayer {
+    /// a player sending `rate` samples a second to `sink`, on the tune's first song
+    pub fn new(nsf: Nsf, rate: u32, sink: Box<dyn AudioSink>) -> Self {
+        let mut bus = DataBus::new();
+        bus.attach_audio(AudioOut::new(rate, nsf.region, sink));
+        let song = nsf.start;
+        let mut p = Self {
+            nsf,
+            cpu: Six502::with_bus(bus),
+            song,
+            period: 0.0,
+            next_play: 0.0,
+            init: false,
+        };
+        p.start(song);
+        p
+    }
+
+    pub fn nsf(&self) -> &Nsf {
+        &self.nsf
+    }
+
+    pub fn song(&self) -> u8 {
+        self.song
+    }
+
+    /// starts song `song` (from 0) from the beginning, on a freshly powered console
+    pub fn start(&mut self, song: u8) {
+        let region = self.nsf.region;
+        let mut bus = DataBus::new();
+        bus.attach_apu(Apu::new());
+        bus.insert_board(Box::new(NsfBoard::new(&self.nsf)), region);
+        if let Some(audio) = self.cpu.bus.detach_audio() {
+            bus.attach_audio(audio);
+        }
+        // the apu as a game would leave it before starting the music
+        for addr in 0x4000..=0x4013 {
+            bus.store_u8(addr, 0);
+        }
+        bus.store_u8(0x4015, 0x00);
+        bus.store_u8(0x4015, 0x0f);
+        bus.store_u8(0x4017, 0x40);
+
+        self.cpu = Six502::with_bus(bus);
+        self.cpu.call(self.nsf.init, song, (region != Region::Ntsc) as u8, RETURN);
+        self.song = song;
+        self.period = self.nsf.period(region) as f64 * region.cpu_clock() / 1e6;
+        self.next_play = 0.0;
+        self.init = true;
+    }
+
+    // runs until INIT or PLAY returns, or `limit` cycles from now
+    fn finish_routine(&mut self, limit: u64) -> Result<(), Box<dyn Error>> {
+        let end = self.cpu.bus.cycles() + limit;
+        while self.cpu.pc() != RETURN && self.cpu.bus.cycles() < end {
+            self.cpu.exec()?;
+        }
+        Ok(())
+    }
+
+    /// plays on for `n` cpu cycles
+    pub fn run_cycles(&mut self, n: u64) -> Result<(), Box<dyn Error>> {
+        if std::mem::take(&mut self.init) {
+            self.finish_routine(INIT_LIMIT)?;
+            if self.cpu.pc() != RETURN {
+                self.cpu.abandon(RETURN);
+            }
+        }
+        let end = self.cpu.bus.cycles() + n;
+        while self.cpu.bus.cycles() < end {
+            let now = self.cpu.bus.cycles() as f64;
+            if self.cpu.pc() != RETURN {
+                // a PLAY that runs over its period misses the calls it runs into
+                self.cpu.exec()?;
+            } else if now >= self.next_play {
+                self.cpu.call(self.nsf.play, 0, 0, RETURN);
+                self.next_play += self.period;
+                while self.next_play <= now {
+                    self.next_play += self.period;
+                }
+            } else {
+                self.cpu.bus.tick();
+            }
+        }
+        Ok(())
+    }
+
+    /// plays on for `seconds`, and hands the sink everything up to there
+    pub fn run(&mut self, seconds: f64) -> Result<(), Box<dyn Error>> {
+        self.run_cycles((seconds * self.nsf.region.cpu_clock()) as u64)?;
+        if let Some(a) = self.cpu.bus.audio.as_mut() {
+            a.flush();
+        }
+        Ok(())
+    }
+}
+
+/// plays `seconds` of song `song` of `nsf` into a WAV file at `rate` samples a second
+pub fn render_wav<W: Write + Seek>(nsf: Nsf, song: u8, seconds: f64, rate: u32, w: W) -> Result<W, Box<dyn Error>> {
+    let samples = Rc::new(RefCell::new(vec![]));
+    let s = samples.clone();
+    let mut player = NsfPlayer::new(nsf, rate, Box::new(move |b: &[f32]| s.borrow_mut().extend_from_slice(b)));
+    player.start(song);
+    player.run(seconds)?;
+    let mut wav = WavWriter::new(w, rate)?;
+    wav.write(&samples.borrow())?;
+    Ok(wav.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // INIT starts a square wave on pulse 1; PLAY counts its calls in $00, in a subroutine
+    #[rustfmt::skip]
+    const PROGRAM: [u8; 0x27] = [
+        0xa9, 0x0f, 0x8d, 0x15, 0x40, // LDA #$0F, STA $4015
+        0xa9, 0xbf, 0x8d, 0x00, 0x40, // LDA #$BF, STA $4000
+        0xa9, 0xfd, 0x8d, 0x02, 0x40, // LDA #$FD, STA $4002
+        0xa9, 0x08, 0x8d, 0x03, 0x40, // LDA #$08, STA $4003
+        0x60,                         // RTS
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0x20, 0x24, 0x80, 0x60,       // $8020: JSR $8024, RTS
+        0xe6, 0x00, 0x60,             // $8024: INC $00, RTS
+    ];
+
+    fn file(data: &[u8]) -> Vec<u8> {
+        let mut b = vec![0; HEADER_LEN];
+        b[..5].copy_from_slice(&MAGIC);
+        b[5] = 1;
+        b[6] = 2;
+        b[7] = 1;
+        b[0x08..0x0e].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x20, 0x80]);
+        b[0x0e..0x12].copy_from_slice(b"Test");
+        b[0x6e..0x70].copy_from_slice(&16639u16.to_le_bytes());
+        b.extend_from_slice(data);
+        b
+    }
+
+    #[test]
+    fn plays() {
+        let samples = Rc::new(RefCell::new(vec![]));
+        let s = samples.clone();
+        let nsf = Nsf::from_bytes(&file(&PROGRAM)).unwrap();
+        assert_eq!((nsf.title.as_str(), nsf.songs, nsf.start, nsf.play), ("Test", 2, 0, 0x8020));
+        let mut player = NsfPlayer::new(nsf, 44100, Box::new(move |b: &[f32]| s.borrow_mut().extend_from_slice(b)));
+        player.run(1.0).unwrap();
+        // PLAY about 60 times a second
+        let calls = player.cpu.bus.load_u8(0x0000);
+        assert!((59..=61).contains(&calls), "{}", calls);
+        let samples = samples.borrow();
+        assert!((44000..=44100).contains(&samples.len()));
+        assert!(samples[4410..].iter().any(|s| s.abs() > 0.05));
+
+        let wav = render_wav(Nsf::from_bytes(&file(&PROGRAM)).unwrap(), 1, 0.1, 44100, Cursor::new(vec![])).unwrap();
+        assert_eq!(wav.into_inner().len(), 44 + 4410 * 2);
+    }
+
+    #[test]
+    fn init_that_never_returns() {
+        // INIT: JMP $8000. PLAY still gets called once INIT is given up on
+        let mut program = PROGRAM;
+        program[..3].copy_from_slice(&[0x4c, 0x00, 0x80]);
+        let nsf = Nsf::from_bytes(&file(&program)).unwrap();
+        let mut player = NsfPlayer::new(nsf, 44100, Box::new(|_: &[f32]| ()));
+        player.run(1.0).unwrap();
+        assert!(player.cpu.bus.cycles() >= INIT_LIMIT);
+        let calls = player.cpu.bus.load_u8(0x0000);
+        assert!((59..=61).contains(&calls), "{}", calls);
+    }
+
+    #[test]
+    fn banks_and_nsfe() {
+        // bank switched, loading at $8100: the first bank starts with $100 bytes of padding
+        let mut b = file(&[0x11; 0x1f00]);
+        b.extend_from_slice(&[0x22; 0x1000]);
+        b[0x08] = 0x00;
+        b[0x09] = 0x81;
+        b[0x70..0x78].copy_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+        let mut board = NsfBoard::new(&Nsf::from_bytes(&b).unwrap());
+        let first = [0x80ff, 0x8100, 0x9000].map(|a| board.cpu_load(a));
+        assert_eq!(first, [Some(0), Some(0x11), Some(0x11)]);
+        board.cpu_store(0x5ff9, 2);
+        assert_eq!(board.cpu_load(0x9000), Some(0x22));
+
+        let chunk = |id: &[u8], data: &[u8]| [&(data.len() as u32).to_le_bytes()[..], id, data].concat();
+        let info = [0x00, 0x80, 0x00, 0x80, 0x20, 0x80, 0x01, chips::VRC6, 3, 1];
+        let e = [
+            &MAGIC_E[..],
+            &chunk(b"INFO", &info),
+            &chunk(b"DATA", &PROGRAM),
+            &chunk(b"auth", b"Song\0Someone\0"),
+            &chunk(b"tlbl", b"One\0Two\0Three\0"),
+            &chunk(b"time", &[&1000i32.to_le_bytes()[..], &(-1i32).to_le_bytes()].concat()),
+            &chunk(b"NEND", &[]),
+        ]
+        .concat();
+        let nsf = Nsf::from_bytes(&e).unwrap();
+        assert_eq!((nsf.region, nsf.songs, nsf.start, nsf.artist.as_str()), (Region::Pal, 3, 1, "Someone"));
+        assert_eq!(nsf.track_names, ["One", "Two", "Three"]);
+        assert_eq!(nsf.track_lengths, [Some(1000), None]);
+        assert_eq!(nsf.period(Region::Pal), 19997);
+        let mut board = NsfBoard::new(&nsf);
+        board.cpu_store(0x9000, 0x8f);
+        board.cpu_store(0x9002, 0x80);
+        assert!(board.audio() > 0.0);
+
+        let missing = [&MAGIC_E[..], &chunk(b"INFO", &info), &chunk(b"NEND", &[])].concat();
+        assert!(matches!(Nsf::from_bytes(&missing), Err(NsfError::MissingChunk("DATA"))));
+        let unknown = [&MAGIC_E[..], &chunk(b"ZZZZ", &[]), &chunk(b"NEND", &[])].concat();
+        assert!(matches!(Nsf::from_bytes(&unknown), Err(NsfError::UnknownChunk(_))));
+    }
+}
@@ -0,0 +1,208 @@
+//! An NTSC filter, after blargg's nes_ntsc: rather than look colours up in a palette, it makes the composite signal
+//! the ppu would have sent to the tv and decodes it the way a tv does. That gets what a palette cannot: colours
+//! bleeding into their neighbours, the rainbow fringes on fine detail that some games drew on purpose, and the crawl
+//! of those fringes from frame to frame.
+//!
+//! The ppu makes its signal with a square wave at 12 phases of the 3.58MHz colour subcarrier, 8 phases per dot. The
+//! hue of a colour picks which 6 of the 12 are high, its brightness picks the two voltages, and each emphasis bit
+//! darkens a third of the phases. A line is 341 dots, which is not a whole number of subcarrier cycles, so the phase
+//! each dot starts on moves from line to line and from frame to frame ([Ppu::frame_phase](super::ppu::Ppu::frame_phase)).
+//! The decoder takes the brightness as the average over one subcarrier cycle and the colour as the subcarrier's
+//! part of the signal, and is all on the cpu: a few multiplies per output pixel, from tables.
+//! [reference](https://www.nesdev.org/wiki/NTSC_video)
+use super::ppu::{HEIGHT, WIDTH};
+use std::f32::consts::PI;
+
+// the signal's voltages: the low and high levels of each brightness, then black and white to scale them by
+const LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+const EMPHASIS: f32 = 0.746;
+// the tv takes its reference phase from the colour burst, which the ppu sends as hue 8; this lines the decoder up
+// with it, in degrees
+const BURST: f32 = 120.0;
+
+// the signal is 8 samples a dot, and the output 2 pixels a dot
+const SAMPLES: usize = 8;
+/// the width of the filtered picture
+pub const OUT_WIDTH: usize = WIDTH * 2;
+
+/// What the tv's knobs are set to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NtscSettings {
+    /// -1 (blurry) to 1 (sharp, with more artifacts). 0 averages brightness over a whole subcarrier cycle
+    pub sharpness: f32,
+    /// -1 (black and white) to 1 (twice the colour)
+    pub saturation: f32,
+    /// in degrees
+    pub hue: f32,
+    /// let the artifacts move from frame to frame, as they do on a tv. Off, every frame is decoded as if it started on
+    /// the same phase, which is steadier to look at
+    pub dot_crawl: bool,
+}
+
+impl Default for NtscSettings {
+    fn default() -> Self {
+        Self {
+            sharpness: 0.0,
+            saturation: 0.0,
+            hue: 0.0,
+            dot_crawl: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NtscFilter {
+    settings: NtscSettings,
+    // the signal of each of the 512 colours at each of the 12 phases
+    signal: Vec<[f32; 12]>,
+    // the subcarrier, at each phase, rotated by the hue setting
+    carrier: [(f32, f32); 12],
+}
+
+impl Default for NtscFilter {
+    fn default() -> Self {
+        Self::new(NtscSettings::default())
+    }
+}
+
+impl NtscFilter {
+    pub fn new(settings: NtscSettings) -> Self {
+        let in_phase = |hue: u16, phase: usize| (hue as usize + phase) % 12 < 6;
+        let signal = (0..512u16)
+            .map(|color| {
+                let (hue, level, emphasis) = (color & 0x0f, (color >> 4) as usize & 0x03, color >> 6);
+                // $xE and $xF are black whatever the brightness, $x0 has no colour, $xD is all low
+                let (lo, hi) = match hue {
+                    0 => (HIGH[level], HIGH[level]),
+                    13 => (LOW[level], LOW[level]),
+                    14 | 15 => (LOW[1], LOW[1]),
+                    _ => (LOW[level], HIGH[level]),
+                };
+                let mut s = [0.0; 12];
+                for (phase, v) in s.iter_mut().enumerate() {
+                    let mut level = if in_phase(hue, phase) { hi } else { lo };
+                    // red emphasis darkens the phases of hue 0, green those of hue 4, blue those of hue 8
+                    let darkened = (0..3).any(|bit| emphasis & (1 << bit) != 0 && in_phase(bit * 4, phase));
+                    if darkened && hue < 14 {
+                        level *= EMPHASIS;
+                    }
+                    *v = (level - BLACK) / (WHITE - BLACK);
+                }
+                s
+            })
+            .collect();
+        let mut carrier = [(0.0, 0.0); 12];
+        for (phase, c) in carrier.iter_mut().enumerate() {
+            let angle = PI * phase as f32 / 6.0 + (settings.hue + BURST).to_radians();
+            *c = (angle.cos(), angle.sin());
+        }
+        Self {
+            settings,
+            signal,
+            carrier,
+        }
+    }
+
+    pub fn settings(&self) -> NtscSettings {
+        self.settings
+    }
+
+    /// filters a frame from the ppu, which started on subcarrier phase `phase` (dots mod 3). The picture is
+    /// [OUT_WIDTH] by [HEIGHT], three bytes a pixel
+    pub fn render(&self, frame: &[u16], phase: u8) -> Vec<u8> {
+        let phase = if self.settings.dot_crawl { phase as usize } else { 0 };
+        let mut out = Vec::with_capacity(OUT_WIDTH * HEIGHT * 3);
+        let mut line = vec![0.0; WIDTH * SAMPLES];
+        let mut phases = vec![0; WIDTH * SAMPLES];
+        for (y, dots) in frame.chunks(WIDTH).enumerate() {
+            // dot 1 draws the first pixel, and each line is 341 dots on from the last
+            let start = (phase + y * 341 + 1) * SAMPLES;
+            for (i, (s, p)) in line.iter_mut().zip(phases.iter_mut()).enumerate() {
+                *p = (start + i) % 12;
+                *s = self.signal[dots[i / SAMPLES] as usize & 0x1ff][*p];
+            }
+            for x in 0..OUT_WIDTH {
+                out.extend_from_slice(&self.decode(&line, &phases, x * SAMPLES / 2 + SAMPLES / 4));
+            }
+        }
+        out
+    }
+
+    // the pixel centred on sample `at`
+    fn decode(&self, line: &[f32], phases: &[usize], at: usize) -> [u8; 3] {
+        let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+        let (mut near, mut near_n) = (0.0, 0);
+        for k in at as isize - 6..at as isize + 6 {
+            // beyond the ends of the line is the border, black
+            let Some(&s) = usize::try_from(k).ok().and_then(|k| line.get(k)) else {
+                continue;
+            };
+            let (c, sn) = self.carrier[phases[k as usize]];
+            y += s;
+            i += s * c;
+            q += s * sn;
+            if (at as isize - 2..at as isize + 2).contains(&k) {
+                near += s;
+                near_n += 1;
+            }
+        }
+        y /= 12.0;
+        if near_n > 0 {
+            y += self.settings.sharpness * (near / near_n as f32 - y);
+        }
+        // the colour is the average of the signal times the carrier
+        let gain = 1.0 / 12.0 * (1.0 + self.settings.saturation);
+        let (i, q) = (i * gain, q * gain);
+        let rgb = [
+            y + 0.956 * i + 0.621 * q,
+            y - 0.272 * i - 0.647 * q,
+            y - 1.106 * i + 1.703 * q,
+        ];
+        rgb.map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the middle pixel of a line of `dots`
+    fn middle(f: &NtscFilter, dots: &[u16], phase: u8) -> [u8; 3] {
+        let line: Vec<u16> = dots.iter().copied().cycle().take(WIDTH).collect();
+        let rgb = f.render(&line, phase);
+        [rgb[WIDTH * 3], rgb[WIDTH * 3 + 1], rgb[WIDTH * 3 + 2]]
+    }
+
+    #[test]
+    fn decodes_colours() {
+        let f = NtscFilter::default();
+        let [r, g, b] = middle(&f, &[0x16], 0);
+        assert!(r > 2 * g && r > 2 * b);
+        let [r, g, b] = middle(&f, &[0x1a], 0);
+        assert!(g > 2 * r && g > b);
+        let [r, g, b] = middle(&f, &[0x12], 0);
+        assert!(b > 2 * r && b > 2 * g);
+        assert_eq!(middle(&f, &[0x30], 0), [255, 255, 255]);
+        assert_eq!(middle(&f, &[0x0f], 0), [0, 0, 0]);
+        // red emphasis takes the blue out of a grey
+        let [r, _, b] = middle(&f, &[0x10 | 1 << 6], 0);
+        assert!(r > b);
+    }
+
+    #[test]
+    fn artifacts_crawl() {
+        // alternating black and white dots come out coloured, differently on each phase
+        let stripes = [0x0f, 0x30];
+        let f = NtscFilter::default();
+        let frames: Vec<_> = (0..3).map(|phase| middle(&f, &stripes, phase)).collect();
+        assert!(frames[0] != frames[1] && frames[1] != frames[2]);
+        let steady = NtscFilter::new(NtscSettings {
+            dot_crawl: false,
+            ..Default::default()
+        });
+        assert_eq!(middle(&steady, &stripes, 0), middle(&steady, &stripes, 1));
+    }
+}
@@ -0,0 +1,122 @@
+//! Turning the ppu's colours into RGB.
+//! The ppu never produces RGB: it generates a composite video signal straight from a 6 bit colour number (a hue in
+//! the low four bits, a brightness in the top two), and the tv decodes that as it likes. So there is no one true
+//! palette, just measurements and models of what the signal looks like on a typical tv, and emulators let you pick.
+//! The three emphasis bits of PPUMASK darken the other two thirds of the colour wheel, which is a palette of 512.
+//!
+//! A `.pal` file is just RGB triples: 64 of them, one per colour, or 512, one per colour per emphasis setting, the
+//! settings in order from 0 to 7. For 64 colour palettes the emphasised colours are worked out here, by darkening the
+//! channels that are not emphasised.
+//! [reference](https://www.nesdev.org/wiki/PPU_palettes)
+use std::{error::Error, fmt, fs, path::Path};
+
+// how much each emphasis bit darkens the channels it does not emphasise
+const ATTENUATION: f32 = 0.816;
+
+/// a widely used 2C02 palette
+#[rustfmt::skip]
+const DEFAULT: [[u8; 3]; 64] = [
+    [84, 84, 84], [0, 30, 116], [8, 16, 144], [48, 0, 136], [68, 0, 100], [92, 0, 48], [84, 4, 0], [60, 24, 0],
+    [32, 42, 0], [8, 58, 0], [0, 64, 0], [0, 60, 0], [0, 50, 60], [0, 0, 0], [0, 0, 0], [0, 0, 0],
+    [152, 150, 152], [8, 76, 196], [48, 50, 236], [92, 30, 228], [136, 20, 176], [160, 20, 100], [152, 34, 32], [120, 60, 0],
+    [84, 90, 0], [40, 114, 0], [8, 124, 0], [0, 118, 40], [0, 102, 120], [0, 0, 0], [0, 0, 0], [0, 0, 0],
+    [236, 238, 236], [76, 154, 236], [120, 124, 236], [176, 98, 236], [228, 84, 236], [236, 88, 180], [236, 106, 100], [212, 136, 32],
+    [160, 170, 0], [116, 196, 0], [76, 208, 32], [56, 204, 108], [56, 180, 204], [60, 60, 60], [0, 0, 0], [0, 0, 0],
+    [236, 238, 236], [168, 204, 236], [188, 188, 236], [212, 178, 236], [236, 174, 236], [236, 174, 212], [236, 180, 176], [228, 196, 144],
+    [204, 210, 120], [180, 222, 120], [168, 226, 144], [152, 226, 180], [160, 214, 228], [160, 162, 160], [0, 0, 0], [0, 0, 0],
+];
+
+#[derive(Debug)]
+pub enum PaletteError {
+    /// neither 64 nor 512 colours
+    BadLength(usize),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteError::BadLength(n) => {
+                write!(f, "a palette is 192 or 1536 bytes (64 or 512 colours), not {}", n)
+            }
+        }
+    }
+}
+
+impl Error for PaletteError {}
+
+/// The RGB of every colour the ppu can put in a frame: a colour number (0-63) with the emphasis bits above it
+#[derive(Debug, Clone)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::with_emphasis(&DEFAULT)
+    }
+}
+
+impl Palette {
+    pub fn open<T: AsRef<Path>>(path: T) -> Result<Self, Box<dyn Error>> {
+        let b = fs::read(path)?;
+        Ok(Self::from_bytes(&b)?)
+    }
+
+    /// reads the contents of a `.pal` file
+    pub fn from_bytes(b: &[u8]) -> Result<Self, PaletteError> {
+        let colors: Vec<[u8; 3]> = b.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect();
+        match (b.len() % 3, colors.len()) {
+            (0, 64) => Ok(Self::with_emphasis(&colors)),
+            (0, 512) => Ok(Self { colors }),
+            _ => Err(PaletteError::BadLength(b.len())),
+        }
+    }
+
+    // the 512 colours of a 64 colour palette. Bit 0 of the emphasis is red, 1 green, 2 blue
+    fn with_emphasis(base: &[[u8; 3]]) -> Self {
+        let colors = (0..8)
+            .flat_map(|emphasis: usize| {
+                base.iter().map(move |rgb| {
+                    let mut out = *rgb;
+                    for (channel, v) in out.iter_mut().enumerate() {
+                        let darkened = (0..3).filter(|&bit| emphasis & (1 << bit) != 0 && bit != channel).count();
+                        *v = (*v as f32 * ATTENUATION.powi(darkened as i32)).round() as u8;
+                    }
+                    out
+                })
+            })
+            .collect();
+        Self { colors }
+    }
+
+    /// the RGB of one dot of [Ppu::frame](super::ppu::Ppu::frame)
+    pub fn rgb(&self, dot: u16) -> [u8; 3] {
+        self.colors[dot as usize & 0x1ff]
+    }
+
+    /// a whole frame as RGB, three bytes a dot, as the image writers take it
+    pub fn frame_rgb(&self, frame: &[u16]) -> Vec<u8> {
+        frame.iter().flat_map(|&dot| self.rgb(dot)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emphasis_and_files() {
+        let p = Palette::default();
+        assert_eq!(p.rgb(0x30), [236, 238, 236]);
+        // red emphasis darkens green and blue; all three darken everything twice
+        assert_eq!(p.rgb(0x30 | 1 << 6), [236, 194, 193]);
+        assert_eq!(p.rgb(0x30 | 7 << 6), [157, 158, 157]);
+
+        let grey: Vec<u8> = (0..64u8).flat_map(|i| [i, i, i]).collect();
+        let p = Palette::from_bytes(&grey).unwrap();
+        assert_eq!(p.frame_rgb(&[0x21, 0x21 | 4 << 6]), [33, 33, 33, 27, 27, 33]);
+        let full: Vec<u8> = (0..512u16).flat_map(|i| [(i >> 6) as u8, i as u8 & 0x3f, 0]).collect();
+        assert_eq!(Palette::from_bytes(&full).unwrap().rgb(0x1c5), [7, 5, 0]);
+        assert!(Palette::from_bytes(&grey[..190]).is_err());
+    }
+}
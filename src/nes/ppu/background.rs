@@ -0,0 +1,91 @@
+//! Background rendering.
+//! Every 8 dots the ppu fetches one tile's worth of data, two dots per access: the nametable byte (which tile), the
+//! attribute byte (which palette), and the two bit planes of the tile's row from the pattern table. The planes go
+//! into the low halves of two 16 bit shift registers, the palette into two more, and every dot all four shift left
+//! once; the pixel on screen is the bit fine x places to the left of the top. That is why a line starts with the
+//! fetches for its first two tiles at dots 321-336 of the line before.
+//! [reference](https://www.nesdev.org/wiki/PPU_rendering)
+use super::{ctrl, Ppu};
+use crate::nes::mapper::Mapper;
+
+/// The background fetch latches and shift registers
+#[derive(Debug, Default)]
+pub(super) struct Pipeline {
+    // latched by the fetches, for the next tile
+    tile: u8,
+    attr: u8,
+    lo: u8,
+    hi: u8,
+    // pattern and palette bits, one per dot, the next dot in bit 15
+    pattern: [u16; 2],
+    palette: [u16; 2],
+}
+
+impl Pipeline {
+    // moves the latched tile into the low halves of the shift registers
+    fn reload(&mut self) {
+        self.pattern[0] = (self.pattern[0] & 0xff00) | self.lo as u16;
+        self.pattern[1] = (self.pattern[1] & 0xff00) | self.hi as u16;
+        // the palette only changes between tiles, so its registers get all 0s or all 1s
+        let fill = |bit: u8| if self.attr & bit != 0 { 0x00ff } else { 0x0000 };
+        self.palette[0] = (self.palette[0] & 0xff00) | fill(1);
+        self.palette[1] = (self.palette[1] & 0xff00) | fill(2);
+    }
+
+    pub(super) fn shift(&mut self) {
+        self.pattern.iter_mut().for_each(|r| *r <<= 1);
+        self.palette.iter_mut().for_each(|r| *r <<= 1);
+    }
+
+    /// the (palette, pixel) of the dot coming out of the registers, `fine_x` dots in
+    pub(super) fn pixel(&self, fine_x: u8) -> (u8, u8) {
+        let bit = 0x8000 >> fine_x;
+        let get = |r: &[u16; 2]| (r[0] & bit != 0) as u8 | ((r[1] & bit != 0) as u8) << 1;
+        (get(&self.palette), get(&self.pattern))
+    }
+}
+
+impl Ppu {
+    /// the background's share of a rendering dot: fetches, and the moves of `v` that go with them
+    pub(super) fn fetch_background(&mut self, cart: &mut dyn Mapper) {
+        let dot = self.dot;
+        // the registers shift on the dots that draw, and on those that prefetch the next line's first two tiles. The
+        // fetch of a tile's nametable byte is counted here as the last access of the tile before it, so each access
+        // happens a dot late, and the tiles come out right
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            self.bg.shift();
+            match (dot - 1) % 8 {
+                0 => {
+                    self.bg.reload();
+                    self.bg.tile = self.read(0x2000 | (self.v & 0x0fff), cart);
+                }
+                2 => {
+                    let v = self.v;
+                    let addr = 0x23c0 | (v & 0x0c00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                    let shift = ((v >> 4) & 0x04) | (v & 0x02);
+                    self.bg.attr = (self.read(addr, cart) >> shift) & 0x03;
+                }
+                4 => self.bg.lo = self.read(self.pattern_addr(), cart),
+                6 => self.bg.hi = self.read(self.pattern_addr() + 8, cart),
+                7 => self.increment_x(),
+                _ => (),
+            }
+        }
+        match dot {
+            256 => self.increment_y(),
+            257 => self.copy_x(),
+            // a second nametable fetch nobody uses (the MMC5 counts them)
+            339 => {
+                self.read(0x2000 | (self.v & 0x0fff), cart);
+            }
+            280..=304 if self.scanline == self.pre_render_line() => self.copy_y(),
+            _ => (),
+        }
+    }
+
+    // the low plane of the latched tile's row `v` is on
+    fn pattern_addr(&self) -> u16 {
+        let table = if self.ctrl & ctrl::BG_TABLE != 0 { 0x1000 } else { 0 };
+        table | (self.bg.tile as u16) << 4 | (self.v >> 12) & 0x07
+    }
+}
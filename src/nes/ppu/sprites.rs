@@ -0,0 +1,213 @@
+//! Sprites.
+//! OAM holds 64 sprites of four bytes each: y (the line above the top of the sprite), tile, attributes (`VHP...CC`:
+//! vertical and horizontal flip, behind the background, palette) and x. While drawing each line the ppu looks through
+//! all 64 for the ones on the next line and copies the first eight into secondary OAM; at dots 257-320 it fetches
+//! their patterns, and during the next line it draws them. Earlier sprites win over later ones, so sprite 0 is always
+//! on top.
+//! A ninth sprite on a line should set the overflow flag, but the search that looks for it is broken: after the
+//! eighth hit it moves on to the next sprite and to the next byte within the sprite at the same time, so it compares
+//! tiles, attributes and x positions against the line as if they were y positions. It misses real overflows and finds
+//! false ones, and games that depend on it have to live with both.
+//! Sprite 0 hit is set on the first dot where an opaque pixel of sprite 0 is drawn over an opaque background pixel.
+//! [reference](https://www.nesdev.org/wiki/PPU_sprite_evaluation) and [OAM](https://www.nesdev.org/wiki/PPU_OAM)
+use super::{ctrl, mask, status, Ppu, HEIGHT};
+use crate::nes::mapper::Mapper;
+
+// the attribute bits that exist; the other three read back as 0
+const ATTR_BITS: u8 = 0xe3;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Slot {
+    attr: u8,
+    x: u8,
+    lo: u8,
+    hi: u8,
+}
+
+#[derive(Debug)]
+pub(super) struct Sprites {
+    pub(super) oam: [u8; 0x100],
+    pub(super) addr: u8,
+    // the sprites found on the next line, and whether sprite 0 is among them
+    secondary: [[u8; 4]; 8],
+    found: usize,
+    zero_found: bool,
+    // the sprites being drawn on this line, patterns fetched
+    slots: [Slot; 8],
+    count: usize,
+    zero_on_line: bool,
+}
+
+impl Default for Sprites {
+    fn default() -> Self {
+        Self {
+            oam: [0; 0x100],
+            addr: 0,
+            secondary: [[0xff; 4]; 8],
+            found: 0,
+            zero_found: false,
+            slots: Default::default(),
+            count: 0,
+            zero_on_line: false,
+        }
+    }
+}
+
+impl Sprites {
+    pub(super) fn write_oam(&mut self, v: u8) {
+        let v = if self.addr & 0x03 == 2 { v & ATTR_BITS } else { v };
+        self.oam[self.addr as usize] = v;
+        self.addr = self.addr.wrapping_add(1);
+    }
+}
+
+impl Ppu {
+    fn sprite_height(&self) -> u16 {
+        if self.ctrl & ctrl::TALL_SPRITES != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    // the row of a sprite at `y` that the line after this one shows, if it shows one
+    fn sprite_row(&self, y: u8) -> Option<u16> {
+        let row = self.scanline.wrapping_sub(y as u16);
+        (row < self.sprite_height()).then_some(row)
+    }
+
+    /// a cpu read of OAMDATA
+    pub(super) fn read_oam(&self) -> u8 {
+        // while secondary OAM is being cleared, at the start of each rendered line, reads see the $FF being written
+        if self.rendering() && (1..=64).contains(&self.dot) {
+            0xff
+        } else {
+            self.sprites.oam[self.sprites.addr as usize]
+        }
+    }
+
+    /// a cpu write to OAMDATA
+    pub(super) fn write_oam(&mut self, v: u8) {
+        if self.rendering() {
+            // the ppu is using OAM. The write is lost, but it bumps the address the way evaluation does
+            self.sprites.addr = self.sprites.addr.wrapping_add(4);
+        } else {
+            self.sprites.write_oam(v);
+        }
+    }
+
+    /// finds the sprites on the next line. The hardware spreads this over dots 65-256; we do it all at once at the end
+    pub(super) fn evaluate_sprites(&mut self) {
+        let s = &mut self.sprites;
+        s.found = 0;
+        s.zero_found = false;
+        s.secondary = [[0xff; 4]; 8];
+        if self.scanline >= HEIGHT as u16 {
+            return;
+        }
+        let mut n = 0;
+        while n < 64 && self.sprites.found < 8 {
+            let i = n * 4;
+            if self.sprite_row(self.sprites.oam[i]).is_some() {
+                let s = &mut self.sprites;
+                s.secondary[s.found].copy_from_slice(&s.oam[i..i + 4]);
+                s.found += 1;
+                s.zero_found |= n == 0;
+            }
+            n += 1;
+        }
+        // looking for a ninth, wrongly: m should stay at 0
+        let mut m = 0;
+        while n < 64 {
+            if self.sprite_row(self.sprites.oam[n * 4 + m]).is_some() {
+                self.status |= status::OVERFLOW;
+                break;
+            }
+            n += 1;
+            m = (m + 1) & 3;
+        }
+    }
+
+    /// the sprites' share of a rendering dot: evaluation, and the pattern fetches at dots 257-320
+    pub(super) fn fetch_sprites(&mut self, cart: &mut dyn Mapper) {
+        match self.dot {
+            256 => self.evaluate_sprites(),
+            257..=320 => {
+                self.sprites.addr = 0;
+                let slot = (self.dot - 257) as usize / 8;
+                match (self.dot - 257) % 8 {
+                    // the two nametable fetches of a background tile, here for nothing
+                    0 | 2 => {
+                        self.read(0x2000 | (self.v & 0x0fff), cart);
+                    }
+                    4 => {
+                        let addr = self.sprite_pattern_addr(slot);
+                        self.sprites.slots[slot].lo = self.sprite_pattern(addr, slot, cart);
+                    }
+                    6 => {
+                        let addr = self.sprite_pattern_addr(slot) + 8;
+                        self.sprites.slots[slot].hi = self.sprite_pattern(addr, slot, cart);
+                    }
+                    7 if slot == 7 => {
+                        let s = &mut self.sprites;
+                        s.count = s.found;
+                        s.zero_on_line = s.zero_found;
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // the pattern byte of `slot`'s sprite at `addr`, flipped if the sprite is. Empty slots fetch tile $FF, and draw
+    // nothing
+    fn sprite_pattern(&mut self, addr: u16, slot: usize, cart: &mut dyn Mapper) -> u8 {
+        let v = self.read(addr, cart);
+        let [_, _, attr, x] = self.sprites.secondary[slot];
+        self.sprites.slots[slot].attr = attr;
+        self.sprites.slots[slot].x = x;
+        if slot >= self.sprites.found {
+            0
+        } else if attr & 0x40 != 0 {
+            v.reverse_bits()
+        } else {
+            v
+        }
+    }
+
+    fn sprite_pattern_addr(&self, slot: usize) -> u16 {
+        let [y, tile, attr, _] = self.sprites.secondary[slot];
+        let height = self.sprite_height();
+        let mut row = self.sprite_row(y).unwrap_or(0);
+        if attr & 0x80 != 0 {
+            row = height - 1 - row;
+        }
+        if height == 16 {
+            // 8x16 sprites pick their table with bit 0 of the tile, and are two tiles one above the other
+            let table = (tile as u16 & 1) << 12;
+            let tile = (tile & 0xfe) as u16 + (row >> 3);
+            table | tile << 4 | (row & 7)
+        } else {
+            let table = if self.ctrl & ctrl::SPRITE_TABLE != 0 { 0x1000 } else { 0 };
+            table | (tile as u16) << 4 | row
+        }
+    }
+
+    /// the frontmost opaque sprite pixel at `x` on this line: (palette, pixel, behind the background, is sprite 0)
+    pub(super) fn sprite_pixel(&self, x: usize) -> Option<(u8, u8, bool, bool)> {
+        if self.mask & mask::SPRITES == 0 || (x < 8 && self.mask & mask::SPRITES_LEFT == 0) {
+            return None;
+        }
+        let s = &self.sprites;
+        s.slots[..s.count].iter().enumerate().find_map(|(i, slot)| {
+            let offset = x.wrapping_sub(slot.x as usize);
+            if offset >= 8 {
+                return None;
+            }
+            let bit = 0x80 >> offset;
+            let pix = (slot.lo & bit != 0) as u8 | ((slot.hi & bit != 0) as u8) << 1;
+            (pix != 0).then(|| (4 + (slot.attr & 0x03), pix, slot.attr & 0x20 != 0, i == 0 && s.zero_on_line))
+        })
+    }
+}
@@ -0,0 +1,162 @@
+//! Regions. The console was built around the tv system it was sold for, and everything that counts time follows
+//! from that: both chips divide one master clock, the ppu draws as many lines as the tv wants per field, and the apu's
+//! timers are tuned so notes stay in tune at the slower clock.
+//!
+//! |                      | NTSC (2C02)  | PAL (2C07)   | Dendy        |
+//! |----------------------|--------------|--------------|--------------|
+//! | master clock         | 21.477272MHz | 26.601712MHz | 26.601712MHz |
+//! | cpu divider          | 12           | 16           | 15           |
+//! | ppu divider          | 4            | 5            | 5            |
+//! | ppu dots per cycle   | 3            | 3.2          | 3            |
+//! | scanlines            | 262          | 312          | 312          |
+//! | vblank starts        | 241          | 241          | 291          |
+//! | vblank lines         | 20           | 70           | 20           |
+//! | odd frames short     | yes          | no           | no           |
+//!
+//! The Dendy clones keep the NTSC vblank and apu tables, and make up the extra lines of a PAL field before vblank,
+//! so NTSC games run on them at PAL speed with little breaking. PAL releases, on the other hand, count on the long
+//! vblank and the retuned apu, which is why they need their own setting.
+//! [reference](https://www.nesdev.org/wiki/Cycle_reference_chart)
+use super::cartridge::Timing;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl From<Timing> for Region {
+    /// the region a cartridge's header asks for. Games that run on either get NTSC
+    fn from(t: Timing) -> Self {
+        match t {
+            Timing::Ntsc | Timing::Multi => Region::Ntsc,
+            Timing::Pal => Region::Pal,
+            Timing::Dendy => Region::Dendy,
+        }
+    }
+}
+
+/// what the runner's `--region` option takes: `ntsc`, `pal` or `dendy`
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            "dendy" => Ok(Region::Dendy),
+            other => Err(format!("no region called {:?}, only ntsc, pal and dendy", other)),
+        }
+    }
+}
+
+impl Region {
+    /// in Hz
+    pub fn master_clock(self) -> f64 {
+        match self {
+            Region::Ntsc => 21_477_272.0,
+            Region::Pal | Region::Dendy => 26_601_712.0,
+        }
+    }
+
+    /// master clocks per cpu cycle
+    pub fn cpu_divider(self) -> u32 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    /// master clocks per ppu dot
+    pub fn ppu_divider(self) -> u32 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal | Region::Dendy => 5,
+        }
+    }
+
+    /// the cpu clock, in Hz
+    pub fn cpu_clock(self) -> f64 {
+        self.master_clock() / self.cpu_divider() as f64
+    }
+
+    pub fn scanlines(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// the line the vblank flag (and NMI) comes up on
+    pub fn vblank_line(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    /// lines from the start of vblank to the pre-render line, which ends it
+    pub fn vblank_lines(self) -> u16 {
+        self.scanlines() - 1 - self.vblank_line()
+    }
+
+    /// whether the pre-render line of every other frame is a dot short when rendering
+    pub fn skips_dot(self) -> bool {
+        self == Region::Ntsc
+    }
+
+    /// frames per second, near enough: the skipped dot is left out
+    pub fn frame_rate(self) -> f64 {
+        let dots = 341.0 * self.scanlines() as f64;
+        self.master_clock() / (dots * self.ppu_divider() as f64)
+    }
+
+    /// the cpu cycle each step of the apu frame counter happens on, counted from the start of the sequence: the 4 step
+    /// one (the IRQ comes with the last) and the 5 step one
+    pub fn frame_counter_steps(self) -> ([u32; 4], [u32; 5]) {
+        match self {
+            Region::Ntsc | Region::Dendy => ([7457, 14913, 22371, 29829], [7457, 14913, 22371, 29829, 37281]),
+            Region::Pal => ([8313, 16627, 24939, 33253], [8313, 16627, 24939, 33253, 41565]),
+        }
+    }
+
+    /// the noise channel's timer periods, in cpu cycles
+    pub fn noise_periods(self) -> &'static [u16; 16] {
+        const NTSC: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+        const PAL: [u16; 16] = [4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778];
+        match self {
+            Region::Ntsc | Region::Dendy => &NTSC,
+            Region::Pal => &PAL,
+        }
+    }
+
+    /// the DMC's timer periods, in cpu cycles
+    pub fn dmc_periods(self) -> &'static [u16; 16] {
+        const NTSC: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+        const PAL: [u16; 16] = [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50];
+        match self {
+            Region::Ntsc | Region::Dendy => &NTSC,
+            Region::Pal => &PAL,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clocks() {
+        assert_eq!(Region::from(Timing::Multi), Region::Ntsc);
+        assert!((Region::Ntsc.cpu_clock() - 1_789_772.7).abs() < 1.0);
+        assert!((Region::Pal.cpu_clock() - 1_662_607.0).abs() < 1.0);
+        assert!((Region::Pal.frame_rate() - 50.007).abs() < 0.001);
+        assert_eq!((Region::Pal.vblank_lines(), Region::Dendy.vblank_lines()), (70, 20));
+        assert_eq!("dendy".parse(), Ok(Region::Dendy));
+        assert!("secam".parse::<Region>().is_err());
+    }
+}
@@ -0,0 +1,149 @@
+//! The ways a host can turn the ppu's frames into pictures: straight through a palette, or through the NTSC filter.
+//! Either way a frame comes out as RGB, three bytes a pixel, ready for [crate::image] or a window.
+use super::cartridge::Cartridge;
+use super::ntsc::{self, NtscFilter};
+use super::palette::Palette;
+use super::ppu::{Ppu, HEIGHT, WIDTH};
+use super::region::Region;
+use crate::bus::DataBus;
+use crate::image::write_png;
+use crate::six502::six502::Six502;
+use crate::Cpu;
+use std::error::Error;
+use std::io::Write;
+use std::str::FromStr;
+
+#[derive(Debug, Clone)]
+pub enum Video {
+    /// a pixel per dot, each colour as the palette has it
+    Palette(Palette),
+    /// two pixels per dot, decoded from a simulated composite signal
+    Ntsc(NtscFilter),
+}
+
+impl Default for Video {
+    fn default() -> Self {
+        Video::Palette(Palette::default())
+    }
+}
+
+impl Video {
+    /// the size of the pictures [Self::render] makes
+    pub fn size(&self) -> (usize, usize) {
+        match self {
+            Video::Palette(_) => (WIDTH, HEIGHT),
+            Video::Ntsc(_) => (ntsc::OUT_WIDTH, HEIGHT),
+        }
+    }
+
+    /// the ppu's last frame
+    pub fn render(&self, ppu: &Ppu) -> Vec<u8> {
+        match self {
+            Video::Palette(p) => p.frame_rgb(ppu.frame()),
+            Video::Ntsc(f) => f.render(ppu.frame(), ppu.frame_phase()),
+        }
+    }
+}
+
+/// what the runner's `--video` option takes: `palette` or `ntsc`
+impl FromStr for Video {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "palette" => Ok(Video::Palette(Palette::default())),
+            "ntsc" => Ok(Video::Ntsc(NtscFilter::default())),
+            other => Err(format!("no video output called {:?}, only palette and ntsc", other)),
+        }
+    }
+}
+
+/// runs `cart` from power on until the ppu has finished `frames` frames, and writes the last of them through `video`
+/// as a PNG. The console runs as `region`, or with `None` as the cartridge's header says
+pub fn render_png<W: Write>(
+    cart: Cartridge,
+    video: &Video,
+    region: Option<Region>,
+    frames: u64,
+    w: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut bus = DataBus::console(cart)?;
+    bus.force_region(region);
+    render_console_png(bus, video, frames, w)
+}
+
+/// like [render_png], on a console the host has put together: with controllers plugged in, say, from
+/// [DataBus::console]
+pub fn render_console_png<W: Write>(bus: DataBus, video: &Video, frames: u64, w: W) -> Result<(), Box<dyn Error>> {
+    let mut cpu = Six502::with_bus(bus);
+    cpu.start()?;
+    while cpu.bus.ppu().map_or(0, Ppu::frames) < frames {
+        cpu.exec()?;
+    }
+    let ppu = cpu.bus.ppu().ok_or("there is no ppu on the bus to take a frame from")?;
+    let (width, height) = video.size();
+    write_png(w, width, height, &video.render(ppu))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::mapper::test_cart;
+
+    #[test]
+    fn sizes() {
+        let ppu = Ppu::new();
+        for v in [Video::default(), Video::Ntsc(NtscFilter::default())] {
+            let (w, h) = v.size();
+            assert_eq!(v.render(&ppu).len(), w * h * 3);
+        }
+    }
+
+    #[test]
+    fn runner() {
+        assert!(matches!("ntsc".parse(), Ok(Video::Ntsc(_))));
+        assert!("crt".parse::<Video>().is_err());
+
+        // NROM-128 that turns rendering on and spins: LDA #$1e, STA $2001, JMP $C005
+        let mut cart = test_cart(0, 0, 0x4000, 0x4000, 0x2000, 0x2000);
+        cart.prg_rom[..8].copy_from_slice(&[0xa9, 0x1e, 0x8d, 0x01, 0x20, 0x4c, 0x05, 0xc0]);
+        cart.prg_rom[0x3ffc..].copy_from_slice(&[0x00, 0xc0, 0x00, 0xc0]);
+        let video = Video::Ntsc(NtscFilter::default());
+        let mut png = vec![];
+        render_png(cart.clone(), &video, None, 2, &mut png).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+        let (w, h) = video.size();
+        assert_eq!(png[16..24], [(w as u32).to_be_bytes(), (h as u32).to_be_bytes()].concat()[..]);
+        // and forced to run as a Dendy
+        let mut dendy = vec![];
+        render_png(cart, &video, Some(Region::Dendy), 2, &mut dendy).unwrap();
+        assert_eq!(&dendy[1..4], b"PNG");
+    }
+
+    #[test]
+    fn pads() {
+        use crate::nes::controller::{buttons, Joypad};
+
+        // strobes the pad, and turns red emphasis on if A is held: LDA #1, STA $4016, LDA #0, STA $4016, LDA $4016,
+        // AND #1, BEQ +5, LDA #$20, STA $2001, JMP $C016
+        let mut cart = test_cart(0, 0, 0x4000, 0x4000, 0x2000, 0x2000);
+        let prg = [
+            0xa9, 0x01, 0x8d, 0x16, 0x40, 0xa9, 0x00, 0x8d, 0x16, 0x40, 0xad, 0x16, 0x40, 0x29, 0x01, 0xf0, 0x05, 0xa9,
+            0x20, 0x8d, 0x01, 0x20, 0x4c, 0x16, 0xc0,
+        ];
+        cart.prg_rom[..prg.len()].copy_from_slice(&prg);
+        cart.prg_rom[0x3ffc..].copy_from_slice(&[0x00, 0xc0, 0x00, 0xc0]);
+        let frame = |held| {
+            let mut pad = Joypad::new();
+            pad.set_buttons(held);
+            let mut bus = DataBus::console(cart.clone()).unwrap();
+            bus.attach_controller(0, Box::new(pad));
+            let mut png = vec![];
+            render_console_png(bus, &Video::default(), 2, &mut png).unwrap();
+            png
+        };
+        assert_eq!(frame(buttons::B), frame(0));
+        assert_ne!(frame(buttons::A), frame(0));
+    }
+}
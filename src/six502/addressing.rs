@@ -5,6 +5,7 @@ use crate::Addressing;
 use super::Six502;
 use crate::ByteAccess;
 use crate::Cpu;
+use crate::bus::BusAccess;
 use std::ops::{AddAssign, BitOrAssign, Index, RangeBounds, Shl, Shr};
 
 #[repr(transparent)]
@@ -82,7 +83,7 @@ pub enum AddressingMode {
 }
 
 
-impl Addressing for Six502 {
+impl<B: BusAccess> Addressing for Six502<B> {
     fn dispatch_load(&mut self, mode: AddressingMode) -> u8 {
         use AddressingMode::*;
         match mode {
@@ -242,22 +243,13 @@ impl Addressing for Six502 {
             }
 
             AbsX_Idxd => {
-                let op = self.load_u16_bump_pc();
-
-                // check if it'll overflow into the zero page
-                let lb_op = op as u8;
-                let (_, carry) = lb_op.overflowing_add(self.x);
                 let addr = self.load_u16_bump_pc();
-                self.addr_bus = addr + (self.x as u16);
+                self.addr_bus = addr.wrapping_add(self.x as u16);
                 self.store_u8(v);
             }
             AbsY_Idxd => {
-                let op = self.load_u16_bump_pc();
-                // check if it'll overflow into the zero page
-                let lb_op = op as u8; // truncates
-                let (_, carry) = lb_op.overflowing_add(self.y);
                 let addr = self.load_u16_bump_pc();
-                self.addr_bus  =addr + (self.y as u16);
+                self.addr_bus = addr.wrapping_add(self.y as u16);
                 self.store_u8(v);
             }
 
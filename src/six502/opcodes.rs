@@ -6,13 +6,14 @@ use crate::{ByteAccess, Addressing};
 use super::WordAccess;
 use crate::macros::impl_addr_modes;
 use crate::Cpu;
+use crate::bus::BusAccess;
 use std::marker::PhantomData;
 use std::ops::{BitAnd, BitOr, BitOrAssign, Shl, Shr};
 
 const BRK: u16 = 0xfffe;
 
 // load/store ops
-impl Six502 {
+impl<B: BusAccess> Six502<B> {
     /// load accumulator with memory. data is transferred from memory into the accumulator
     /// zero flag is set if the acc is zero, otherwise resets
     //  negative flag is set if bit 7 of the accumulator is a 1, otherwise resets
@@ -55,7 +56,7 @@ impl Six502 {
 }
 
 // comparisons
-impl Six502 {
+impl<B: BusAccess> Six502<B> {
     // util for compare operations
     // reg is the register the value v (loaded from memory) will be subtracted from.
     fn compare(&mut self, reg: u8, v: u8) {
@@ -100,7 +101,7 @@ impl Six502 {
 
 // register transfers
 // these ops make use of implied addressing, and are one byte instructions
-impl Six502 {
+impl<B: BusAccess> Six502<B> {
     /// tax transfers accumulator into x register, updating the z and n flags based on the value of a
     pub(super) fn tax(&mut self, _mode: AddressingMode) {
         self.x = self.a;
@@ -141,7 +142,7 @@ impl Six502 {
 
 // stack ops
 // single byte instructions. addressing mode implied
-impl Six502 {
+impl<B: BusAccess> Six502<B> {
     /// transfers the current value of the accumulator the next location on the stack, automatically decrementing the stack to
     /// point to the next empty location.
     pub(super) fn pha(&mut self, _mode: AddressingMode) {
@@ -174,7 +175,7 @@ impl Six502 {
 }
 
 // logical ops
-impl Six502 {
+impl<B: BusAccess> Six502<B> {
     /// The AND instruction performs a bit-by-bit AND operation and stores the result back in the accumulator
     /// Addressing modes: Immediate; Absolute; Zero Page; Absolute,X; Absolute,Y; Zero Page,X; Indexed Indirect; and Indirect Indexed.
     // affects z and n flags
@@ -209,7 +210,7 @@ impl Six502 {
 // In unsigned arithmetic, we need to watch the carry flag to detect errors. The overflow flag is not useful for unsigned ops
 // In signed arithmetic, we need to watch the overflow flag to detect errors. The sign flag is not useful for signed ops
 // the programmer makes this decision basd on what they want. the cpu knows nothing about their intents. it justs sets the flag accordingly
-impl Six502 {
+impl<B: BusAccess> Six502<B> {
     /// Add Memory to Accumulator with Carry
     /// This instruction adds the value of memory and carry from the previous operation to the value of the accumulator and stores the
     /// result in the accumulator.
@@ -314,19 +315,19 @@ impl Six502 {
 }
 
 //incrs and decrs
-impl Six502 {
+impl<B: BusAccess> Six502<B> {
     pub(super) fn inc(&mut self, mode: AddressingMode) {
-        let v = self.dispatch_load(mode);
-        let v = v.wrapping_add(1);
+        let old = self.dispatch_load(mode);
+        let v = old.wrapping_add(1);
         self.update_zn_flags(v);
-        self.dispatch_store(v, mode);
+        self.write_back(old, v, mode);
     }
 
     pub(super) fn dec(&mut self, mode: AddressingMode) {
-        let v = self.dispatch_load(mode);
-        let v = v.wrapping_sub(1);
+        let old = self.dispatch_load(mode);
+        let v = old.wrapping_sub(1);
         self.update_zn_flags(v);
-        self.dispatch_store(v, mode);
+        self.write_back(old, v, mode);
     }
 
     ///   Increment X adds 1 to the current value of the X register.
@@ -361,7 +362,7 @@ impl Six502 {
 
 
 // shifts
-impl Six502 {
+impl<B: BusAccess> Six502<B> {
     pub(super) fn rol(&mut self, mode: AddressingMode) {
         let b= self.dispatch_load(mode);
         let mut res: u8 = b.shl(1);
@@ -371,7 +372,7 @@ impl Six502 {
         self.assert_flag(flags::CARRY, b & 0x80 != 0);
 
         self.update_zn_flags(res);
-        self.dispatch_store( res, mode);
+        self.write_back(b, res, mode);
     }
 
     pub(super) fn asl(&mut self, mode: AddressingMode) {
@@ -380,7 +381,7 @@ impl Six502 {
         self.assert_flag(flags::CARRY, b & 0x80 != 0);
 
         self.update_zn_flags(res);
-        self.dispatch_store( res, mode);
+        self.write_back(b, res, mode);
     }
 
     pub(super) fn ror(&mut self, mode: AddressingMode) {
@@ -391,7 +392,7 @@ impl Six502 {
         }
         self.assert_flag(flags::CARRY, (b & 0x1) != 0);
         self.update_zn_flags(res);
-        self.dispatch_store( res, mode);
+        self.write_back(b, res, mode);
     }
 
     pub(super) fn lsr(&mut self, mode: AddressingMode) {
@@ -399,12 +400,12 @@ impl Six502 {
         let res = b.shr(1);
         self.assert_flag(flags::CARRY, (b & 0x1) != 0);
         self.update_zn_flags(res);
-        self.dispatch_store( res, mode);
+        self.write_back(b, res, mode);
     }
 }
 
 /// jumps and calls
-impl Six502 {
+impl<B: BusAccess> Six502<B> {
     const BRK_VECTOR: u16 = 0xfffe;
 
     /// **Jump** with absolute addressing
@@ -417,9 +418,11 @@ impl Six502 {
 
     /// The other version of jump, but with indirect addressing
     pub(super) fn jmp_indirect(&mut self, _mode: AddressingMode) {
-        let pc = self.load_u16_bump_pc();
+        let ptr = self.load_u16_bump_pc();
+        self.addr_bus = ptr;
         let lo = self.load_u8();
-        self.addr_bus = (pc & 0xff00) | ((pc + 1) & 0x00ff);
+        // the pointer's high byte comes from the same page, even when the low byte is the last of it
+        self.addr_bus = (ptr & 0xff00) | (ptr.wrapping_add(1) & 0x00ff);
         let hi = self.load_u8();
         self.pc = u16::from_le_bytes([lo, hi]);
     }
@@ -428,9 +431,9 @@ impl Six502 {
     /// transfers control of the program counter to a sub- routine location but leaves a return pointer on the stack to allow the
     /// user to return to perform the next instruction in the main program after the subroutine is complete
     pub(super) fn jsr(&mut self, _mode: AddressingMode) {
-        let pc = self.pc;
         let addr = self.load_u16_bump_pc();
-        self.push_u16(pc - 1); // push curr pc-1 to the stack
+        // the address of the JSR's last byte, which the RTS steps past
+        self.push_u16(self.pc.wrapping_sub(1));
         self.pc = addr;
     }
 
@@ -487,29 +490,25 @@ impl Six502 {
 // This is to reduce the number of bytes needed for branching instructions, in effect reducing cpu load.
 // In relative addressing, we add the value in the memory location following the OPCODE to the program counter.  This allows us to
 // specify a new program counter location with only two bytes, one for the OPCODE and one for the value to be added.
-impl Six502 {
+impl<B: BusAccess> Six502<B> {
     /// base routine for branching. cond parameter states that you wan the flag to be either set/unset
     /// If a branch is normally not taken, assume 2 cycles for the branch.
     /// If the branch is normally taken but it does not across the page boundary, assume 3 cycles for the branch.
     /// If the branch crosses over a page boundary, then assume 4 cycles for the  branch.
     pub fn branch(&mut self, flag: u8, cond: bool) {
-        // self.cy+=1;
         // relative addressing. load just one byte.
         // casting the u8 as an i8, and from there to u16 helps create the twos compliment of the number with length 16bits
         let off = self.load_u8_bump_pc() as i8 as u16;
-        let old_pc = self.pc;
-        let mut num_cy = 0;
-        if cond && self.is_flag_set(flag) {
-            self.pc = self.pc.wrapping_add(off);
-            num_cy += 1; // branch was taken. branching truly occured
-        } else {
-            // !cond && !self.is_flag_set(flag)
-            self.pc = self.pc.wrapping_add(off);
-            num_cy += 1; // branch was taken. branching truly occured
+        if self.is_flag_set(flag) != cond {
+            return;
         }
+        let old_pc = self.pc;
+        self.pc = self.pc.wrapping_add(off);
+        // branch was taken. branching truly occured
+        self.bus_cycle();
         if (self.pc & 0xff00) != (old_pc & 0xff00) {
             // crossed page boundary
-            num_cy += 1;
+            self.bus_cycle();
         }
     }
 
@@ -557,7 +556,7 @@ impl Six502 {
 /// Status flag changes
 /// All implied addressing
 /// none of these ops have side effect of affecting other flags
-impl Six502 {
+impl<B: BusAccess> Six502<B> {
     /// resets the carry flag to a 0
     /// typically precedes an `adc` loop. 
     /// IMPLIED addressing
@@ -605,8 +604,158 @@ mod tests {
     use super::*;
     use parameterized::parameterized;
 
+    use crate::bus::watch::{Access, On, WatchHit, Watched, Watchpoint};
+    use crate::six502::ram::Ram;
+    use crate::Cpu;
+
     #[parameterized(inp = {1,2,3}, out ={2,3,4})]
     fn test_adc(inp: i32, out: i32) {
         assert_eq!(inp+1, out);
     }
+
+    // a cpu on 2KB of ram, with `program` at $0300 and about to run it
+    fn boot(program: &[u8]) -> Six502<Ram> {
+        let mut cpu = Six502::with_bus(Ram::new());
+        cpu.bus[0x300..0x300 + program.len()].copy_from_slice(program);
+        cpu.pc = 0x0300;
+        cpu
+    }
+
+    #[test]
+    fn pha_pla() {
+        // LDA #$42, PHA, LDA #$00, PLA
+        let mut cpu = boot(&[0xa9, 0x42, 0x48, 0xa9, 0x00, 0x68]);
+        cpu.exec().unwrap();
+        cpu.exec().unwrap();
+        // pushed to $0100 plus the stack pointer
+        assert_eq!((cpu.bus[0x1fd], cpu.s), (0x42, 0xfc));
+        cpu.exec().unwrap();
+        cpu.exec().unwrap();
+        assert_eq!((cpu.a, cpu.s), (0x42, 0xfd));
+    }
+
+    #[test]
+    fn absolute_operands() {
+        // LDA $0210, LDX #$02, STA $0400,X, LDA #$77
+        let mut cpu = boot(&[0xad, 0x10, 0x02, 0xa2, 0x02, 0x9d, 0x00, 0x04, 0xa9, 0x77]);
+        cpu.bus[0x210] = 0x5a;
+        cpu.exec().unwrap();
+        assert_eq!((cpu.a, cpu.pc), (0x5a, 0x0303));
+        cpu.exec().unwrap();
+        cpu.exec().unwrap();
+        // the store took two operand bytes, so the next instruction is where it should be
+        assert_eq!((cpu.bus[0x402], cpu.pc), (0x5a, 0x0308));
+        cpu.exec().unwrap();
+        assert_eq!(cpu.a, 0x77);
+    }
+
+    #[test]
+    fn jsr_rts() {
+        // JSR $0310, LDA #$55 ... $0310: RTS
+        let mut cpu = boot(&[0x20, 0x10, 0x03, 0xa9, 0x55]);
+        cpu.bus[0x310] = 0x60;
+        cpu.exec().unwrap();
+        assert_eq!((cpu.pc, cpu.s), (0x0310, 0xfb));
+        // the return address is the JSR's last byte
+        assert_eq!((cpu.bus[0x1fd], cpu.bus[0x1fc]), (0x03, 0x02));
+        cpu.exec().unwrap();
+        cpu.exec().unwrap();
+        assert_eq!((cpu.a, cpu.pc, cpu.s), (0x55, 0x0305, 0xfd));
+    }
+
+    #[test]
+    fn jmp_indirect() {
+        let mut cpu = boot(&[0x6c, 0x00, 0x02]);
+        cpu.bus[0x200..0x202].copy_from_slice(&[0x00, 0x04]);
+        cpu.exec().unwrap();
+        assert_eq!(cpu.pc, 0x0400);
+        // a pointer at the end of a page takes its high byte from the start of it
+        let mut cpu = boot(&[0x6c, 0xff, 0x02]);
+        cpu.bus[0x2ff] = 0x34;
+        cpu.bus[0x200] = 0x05;
+        cpu.exec().unwrap();
+        assert_eq!(cpu.pc, 0x0534);
+    }
+
+    #[test]
+    fn branches() {
+        // LDA #$00, BNE +2, BEQ +2, LDA #$11, LDA #$22: BNE falls through, BEQ jumps over the $11
+        let mut cpu = boot(&[0xa9, 0x00, 0xd0, 0x02, 0xf0, 0x02, 0xa9, 0x11, 0xa9, 0x22]);
+        cpu.exec().unwrap();
+        cpu.exec().unwrap();
+        assert_eq!((cpu.pc, cpu.ticked), (0x0304, 2));
+        cpu.exec().unwrap();
+        // a cycle more for taking it
+        assert_eq!((cpu.pc, cpu.ticked), (0x0308, 3));
+        cpu.exec().unwrap();
+        assert_eq!(cpu.a, 0x22);
+        // and another for landing on another page: BCC -$80 from $0302
+        let mut cpu = boot(&[0x90, 0x80]);
+        cpu.exec().unwrap();
+        assert_eq!((cpu.pc, cpu.ticked), (0x0282, 4));
+    }
+
+    #[test]
+    fn rmw_writes_the_old_value_first() {
+        // INC $10: the unmodified $41 goes back before the $42
+        let mut ram = Ram::new();
+        ram[0x300..0x302].copy_from_slice(&[0xe6, 0x10]);
+        ram[0x10] = 0x41;
+        let mut bus = Watched::new(ram);
+        bus.add(Watchpoint::at(0x10, On::WRITE).value(0x41));
+        let mut cpu = Six502::with_bus(bus);
+        cpu.pc = 0x0300;
+        assert!(cpu.exec().is_err());
+        assert_eq!(cpu.bus.inner()[0x10], 0x42);
+    }
+
+    // ram with an IRQ line a test can hold low
+    struct Line(Ram, bool);
+
+    impl BusAccess for Line {
+        fn load_u8(&mut self, addr: u16) -> u8 {
+            self.0.load_u8(addr)
+        }
+
+        fn store_u8(&mut self, addr: u16, v: u8) {
+            self.0.store_u8(addr, v)
+        }
+
+        fn irq(&self) -> bool {
+            self.1
+        }
+    }
+
+    #[test]
+    fn irq_line() {
+        // CLI, NOP, with the handler at $0400. The line is held, but the interrupt waits for the CLI
+        let mut ram = Ram::new();
+        ram[0x300..0x302].copy_from_slice(&[0x58, 0xea]);
+        ram[0x7fe..].copy_from_slice(&[0x00, 0x04]);
+        let mut cpu = Six502::with_bus(Line(ram, true));
+        cpu.pc = 0x0300;
+        cpu.exec().unwrap();
+        assert_eq!(cpu.pc, 0x0301);
+        cpu.exec().unwrap();
+        assert_eq!(cpu.pc, 0x0400);
+        assert!(cpu.is_flag_set(flags::IRQ));
+        // the return address, and the status with the break flag clear
+        assert_eq!((cpu.bus.0[0x1fd], cpu.bus.0[0x1fc], cpu.bus.0[0x1fb] & 0x30), (0x03, 0x01, 0x20));
+    }
+
+    #[test]
+    fn watchpoint_on_interrupt_entry() {
+        // a read watch on the IRQ vector stops the cpu once the interrupt has been taken
+        let mut ram = Ram::new();
+        ram[0x7fe..].copy_from_slice(&[0x00, 0x04]);
+        let mut bus = Watched::new(Line(ram, true));
+        bus.add(Watchpoint::at(0xfffe, On::READ));
+        let mut cpu = Six502::with_bus(bus);
+        cpu.pc = 0x0300;
+        cpu.p &= !flags::IRQ;
+        let hit = cpu.exec().unwrap_err();
+        let hit = hit.downcast_ref::<WatchHit>().unwrap();
+        assert_eq!((hit.access, hit.addr, hit.pc), (Access::Read, 0xfffe, 0x0300));
+        assert_eq!(cpu.pc, 0x0400);
+    }
 }
\ No newline at end of file
@@ -1,7 +1,7 @@
 use super::Six502;
 use crate::{
     macros::impl_deref_mut,
-    bus::BusAccess,
+    bus::{shadow::{Shadow, UninitRead}, BusAccess},
 };
 use core::panic;
 use std::ops::{Deref, DerefMut, Index};
@@ -9,30 +9,62 @@ use std::ops::{Deref, DerefMut, Index};
 #[derive(Debug)]
 pub struct Ram {
     array: [u8; 0x800],
+    // when enabled, tracks which of the 2KB have been written
+    shadow: Option<Shadow>,
 }
 
 impl_deref_mut!(Ram {array, [u8]});
 
 impl Default for Ram {
     fn default() -> Self {
-        todo!()
+        Self::new()
     }
 }
 impl Ram {
-    pub(crate) fn new() -> Self {
+    pub fn new() -> Self {
         Self {
             array: [0u8; 0x800],
+            shadow: None,
         }
     }
+
+    /// starts tracking reads of bytes that happen before the byte is first written.
+    /// The shadow is kept per physical byte, so a write through one mirror initialises the byte for all of them
+    pub fn enable_shadow(&mut self) {
+        self.shadow = Some(Shadow::new(0x800));
+    }
+
+    /// reads of never-written ram seen since the shadow was enabled. Empty if it never was
+    pub fn uninit_reads(&self) -> &[UninitRead] {
+        self.shadow.as_ref().map_or(&[], |s| s.reads())
+    }
+
+    pub fn take_uninit_reads(&mut self) -> Vec<UninitRead> {
+        self.shadow.as_mut().map_or_else(Vec::new, |s| s.take_reads())
+    }
 }
 
 impl BusAccess for Ram {
     // first 8192 bytes are for the ram. the ram is 2048 consecutive bytes mirrored three other times, consecutively
     fn load_u8(&mut self, addr: u16) -> u8 {
-        self[(addr & 0x7ff) as usize]
+        let idx = (addr & 0x7ff) as usize;
+        if let Some(s) = self.shadow.as_mut() {
+            s.read(idx, addr);
+        }
+        self[idx]
     }
 
     fn store_u8(&mut self, addr: u16, val: u8) {
-        self[(addr & 0x7ff) as usize] = val;
+        let idx = (addr & 0x7ff) as usize;
+        if let Some(s) = self.shadow.as_mut() {
+            s.write(idx);
+        }
+        self[idx] = val;
+    }
+
+    fn sync_pc(&mut self, pc: u16) {
+        if let Some(s) = self.shadow.as_mut() {
+            s.sync_pc(pc);
+        }
     }
 }
@@ -5,9 +5,9 @@ use crate::ByteAccess;
 use crate::{AddressingMode, Cpu};
 use super::WordAccess;
 
-use super::{disasm::INSTRUCTIONS, vectors};
+use super::{disasm::INSTRUCTIONS, flags, vectors};
 
-pub struct Six502 {
+pub struct Six502<B: BusAccess = DataBus> {
     /// the major use for the accumulator is transferring data from memory to the accumulator or from the accumulator to memory.
     /// mathematical amd logical operations can then be done to data inside the accumulator. It is where intermediate values are normally  stored
     pub(super) a: u8,
@@ -25,20 +25,26 @@ pub struct Six502 {
     /// flags
     pub(super) p: u8, 
     /// Sixteen bits of address allow access to 65,536 memory locations, each of which, in the MCS650X family, consists of 8 bits of data
-    pub(crate) bus: DataBus,
+    pub(crate) bus: B,
     pub(crate) data: u8,
 
     pub(crate) addr_bus: u16,
+    /// bus cycles the current instruction has ticked so far. Every access ticks one as it happens, and whatever the
+    /// instruction spends inside the cpu is made up once it is done
+    pub(super) ticked: u32,
 }
 
 
-impl ByteAccess for Six502 {
+impl<B: BusAccess> ByteAccess for Six502<B> {
     fn load_u8(&mut self) -> u8 {
-        self.bus.load_u8(self.addr_bus)
+        let v = self.bus.load_u8(self.addr_bus);
+        self.bus_cycle();
+        v
     }
 
     fn store_u8(&mut self, v: u8) {
         self.bus.store_u8(self.addr_bus, v);
+        self.bus_cycle();
     }
 
     fn bump(&mut self) {
@@ -48,8 +54,15 @@ impl ByteAccess for Six502 {
     
 }
 
-impl Default for Six502 {
+impl<B: BusAccess + Default> Default for Six502<B> {
     fn default() -> Self {
+        Self::with_bus(B::default())
+    }
+}
+
+impl<B: BusAccess> Six502<B> {
+    /// a cpu in its power-on state, wired to `bus`
+    pub fn with_bus(bus: B) -> Self {
         Self {
             a: 0,
             x: 0,
@@ -58,15 +71,56 @@ impl Default for Six502 {
             s: 0xfd,
             cy: 0,
             p: 0x24,
-            bus: DataBus::new(),
+            bus,
             addr_bus: 0,
             data: 0,
+            ticked: 0,
         }
     }
+
+    /// what the cpu is wired to
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    /// the address of the next instruction
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// starts the subroutine at `addr` with `a` and `x` loaded, as if a JSR had called it from just before `ret`, so
+    /// its RTS lands on `ret`. For hosts that drive a program's routines themselves, like an NSF player
+    pub fn call(&mut self, addr: u16, a: u8, x: u8, ret: u16) {
+        self.push_u16(ret.wrapping_sub(1));
+        self.a = a;
+        self.x = x;
+        self.pc = addr;
+    }
+
+    /// gives up on whatever the cpu is running, a [Self::call] that never returns say: empties the stack and puts it
+    /// at `ret`, as if the call had returned there
+    pub fn abandon(&mut self, ret: u16) {
+        self.s = 0xfd;
+        self.pc = ret;
+    }
+
+    // the rest of the console's share of the cycle an access was on: the ppu's dots, the apu, the mapper's counters.
+    // Ticking here rather than after the instruction is what puts a $2002 read on the right dot
+    pub(super) fn bus_cycle(&mut self) {
+        self.bus.tick();
+        self.ticked += 1;
+    }
 }
 
-impl Cpu for Six502 {
-    fn new() -> Self {
+impl<B: BusAccess> Cpu for Six502<B> {
+    fn new() -> Self
+    where
+        Self: Default,
+    {
         Default::default()
     }
 
@@ -82,23 +136,10 @@ impl Cpu for Six502 {
         self.data
     }
     
-    // comeback maybe we dont need this
+    /// like [Self::load_u8_bump_pc], twice: the two operand bytes, low first. Does not tick either
     fn load_u16_bump_pc(&mut self) -> u16 {
-        let mut addr = self.pc;
-        self.pc = self.pc.wrapping_add(1);
-        self.data = self.load_u8();
-        self.tick();
-        let lo = self.data;
-        
-        // bump the address in the addr bus
-        self.bump();
-
-        addr = self.pc;
-        self.data = self.load_u8();
-        self.pc = self.pc.wrapping_add(1);
-        self.tick();
-        let hi = self.data;
-
+        let lo = self.load_u8_bump_pc();
+        let hi = self.load_u8_bump_pc();
         u16::from_le_bytes([lo, hi])
     }
 
@@ -131,7 +172,11 @@ impl Cpu for Six502 {
     /// `fetch_op` cannot be an atomic op because the internal operation of [Six502] during `fetch_op` varies
     fn fetch_op(&mut self) {
         //get the current op from the pc
-        self.load_u8_bump_pc();
+        self.addr_bus = self.pc;
+        self.pc = self.pc.wrapping_add(1);
+        // fetched through `fetch_u8` rather than `load_u8` so the bus can tell opcodes from data
+        self.data = self.bus.fetch_u8(self.addr_bus);
+        self.bus_cycle();
     }
 
     /// decodes the op fetched by setting the [Op]'s internal values, i.e. the `addr_mode`, `curr_up`, and `curr_op_num` 
@@ -149,7 +194,30 @@ impl Cpu for Six502 {
     /// and incrementing again after. for a full operation, it may incr 1,2,3 or more times
     /// an instance is LDA absolute addressing. three increments. one for opcode. one for low addr byte. one for high addr byte
     fn exec(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.load_u8_bump_pc();
+        self.ticked = 0;
+        // the interrupt lines are sampled between instructions, NMI first. Taking an interrupt is a step of its own
+        let nmi = self.bus.nmi();
+        if nmi || (self.bus.irq() && !self.is_flag_set(flags::IRQ)) {
+            // the pushes and the vector fetch belong to the instruction that was interrupted
+            self.bus.sync_pc(self.pc);
+            if nmi {
+                self.nmi();
+            } else {
+                self.irq();
+            }
+            // five of the seven cycles were the pushes and the vector
+            while self.ticked < 7 {
+                self.bus_cycle();
+            }
+            // a watchpoint on the stack or a vector stops execution as one an instruction hits does
+            if let Some(hit) = self.bus.take_break() {
+                return Err(Box::new(hit));
+            }
+            return Ok(());
+        }
+        // let the bus know which instruction the coming accesses belong to
+        self.bus.sync_pc(self.pc);
+        self.fetch_op();
         let op = self.data;
 
         match op {
@@ -352,10 +420,18 @@ impl Cpu for Six502 {
 
             _ => unimplemented!("op not unimplemented: {}", op),
         };
-        self.cy = self
-            .cy
-            .wrapping_add(CYCLES[op as usize] as u64);
+        // the accesses have ticked as they went; the cycles with none (the dummy reads we leave out, the internal
+        // operations) come last
+        while self.ticked < CYCLES[op as usize] as u32 {
+            self.bus_cycle();
+        }
+        self.cy = self.cy.wrapping_add(self.ticked as u64);
+        self.cy = self.cy.wrapping_add(self.bus.dma());
 
+        // a watchpoint hit stops execution once the instruction that caused it is done
+        if let Some(hit) = self.bus.take_break() {
+            return Err(Box::new(hit));
+        }
         Ok(())
     }
 
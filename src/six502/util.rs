@@ -4,11 +4,12 @@ use crate::{
     AddressingMode,
 };
 use crate::six502::WordAccess;
+use crate::bus::BusAccess;
 use std::ops::{Add, AddAssign};
 
 const STACK_OFFSET: u16 = 0x0100;
 
-impl Six502 {
+impl<B: BusAccess> Six502<B> {
     /// Tthe concept of interrupt is used to signal the microprocessor that an external event has occurred and the
     /// microprocessor should devote attention to it immediately.  
     /// This technique accomplishes processing in which the microprocessor's program is interrupted and the event that caused the interrupt is serviced.
@@ -19,9 +20,9 @@ impl Six502 {
     // used when a high priority device which cannot afford to Wait during the time interrupts are disabled (using the IRQ).
     // when this line goes from high to low, the microprocessor sets an internal flag  such that at the beginning of
     // the next instruction, no matter what the status of the interrupt disable, the microprocessor performs the interrupt sequence
-    fn nmi(&mut self) {
+    pub(super) fn nmi(&mut self) {
         self.push_u16(self.pc);
-        self.push_u8(self.p);
+        self.push_u8((self.p & !flags::BREAK) | flags::UNUSED);
         // set  the interrrupt disable flag
         self.p |= flags::IRQ;
         self.addr_bus = vectors::NMI;
@@ -29,9 +30,10 @@ impl Six502 {
         self.cy += 7;
     }
 
-    fn irq(&mut self) {
+    // a hardware interrupt pushes the status with the break flag clear, which is how a handler tells it from a BRK
+    pub(super) fn irq(&mut self) {
         self.push_u16(self.pc);
-        self.push_u8(self.p);
+        self.push_u8((self.p & !flags::BREAK) | flags::UNUSED);
         // set  the interrrupt disable flag
         self.p |= flags::IRQ;
         self.addr_bus = vectors::IRQ;
@@ -57,7 +59,7 @@ impl Six502 {
     //  memory location in which data will be directly stored.
     // operations which put data on the stack cause the pointer to be decremented automatically
     pub(super) fn push_u8(&mut self, b: u8) {
-        let addr = STACK_OFFSET + self.s as u16;
+        self.addr_bus = STACK_OFFSET + self.s as u16;
         self.store_u8(b);
         self.s = self.s.wrapping_sub(1);
     }
@@ -65,7 +67,7 @@ impl Six502 {
     // operations which pull data from the stack cause the pointer to be incremented automatically
     // adds 1 to the current value of the stack pointer and uses it to address the stack
     pub(super) fn pull_u8(&mut self) -> u8 {
-        let addr = STACK_OFFSET + self.s as u16 + 1;
+        self.addr_bus = STACK_OFFSET + self.s as u16 + 1;
         let v = self.load_u8();
         self.s = self.s.wrapping_add(1);
         v
@@ -86,6 +88,19 @@ impl Six502 {
         v
     }
 
+    /// the store half of a read-modify-write instruction (inc, dec and the shifts).
+    /// `dispatch_load` left the effective address on the address bus, so the result goes straight back there. The 6502
+    /// spends the cycle before that writing the unmodified value back, and hardware that counts writes (the MMC1's
+    /// serial port, for one) sees both
+    pub(super) fn write_back(&mut self, old: u8, new: u8, mode: AddressingMode) {
+        if let AddressingMode::Acc_Addrs = mode {
+            self.a = new;
+            return;
+        }
+        self.store_u8(old);
+        self.store_u8(new);
+    }
+
     // flag helpers
     // sets the flag provided in the argument
     pub(super) fn set_flag(&mut self, flag: u8) {
@@ -123,7 +138,7 @@ impl Six502 {
     }
 
     // atom does any number of ops and ticks once
-    pub(super) fn atom<F: FnMut(&mut Six502)>(&mut self, mut f: F) {
+    pub(super) fn atom<F: FnMut(&mut Self)>(&mut self, mut f: F) {
         f(self);
         self.cy += 1;
     }
@@ -0,0 +1,72 @@
+//! A writer for 16 bit mono PCM WAV files, with no dependencies.
+//! The header holds the length of the data, which is not known until the end. Rather than ask to be finished, the
+//! writer puts the right lengths in after every write, so a file is always complete, however the program stops.
+use std::io::{self, Seek, SeekFrom, Write};
+
+const HEADER_LEN: u32 = 44;
+
+pub struct WavWriter<W: Write + Seek> {
+    w: W,
+    // bytes of samples so far
+    len: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut w: W, rate: u32) -> io::Result<Self> {
+        w.write_all(b"RIFF")?;
+        w.write_all(&(HEADER_LEN - 8).to_le_bytes())?;
+        w.write_all(b"WAVEfmt ")?;
+        // chunk size, PCM, 1 channel, the rate, bytes per second, bytes per frame, bits per sample
+        w.write_all(&16u32.to_le_bytes())?;
+        w.write_all(&1u16.to_le_bytes())?;
+        w.write_all(&1u16.to_le_bytes())?;
+        w.write_all(&rate.to_le_bytes())?;
+        w.write_all(&(rate * 2).to_le_bytes())?;
+        w.write_all(&2u16.to_le_bytes())?;
+        w.write_all(&16u16.to_le_bytes())?;
+        w.write_all(b"data")?;
+        w.write_all(&0u32.to_le_bytes())?;
+        Ok(Self { w, len: 0 })
+    }
+
+    /// appends samples, -1.0 to 1.0. Anything outside is clipped
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let pcm: Vec<u8> = samples
+            .iter()
+            .flat_map(|s| ((s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())
+            .collect();
+        self.w.write_all(&pcm)?;
+        self.len += pcm.len() as u32;
+
+        self.w.seek(SeekFrom::Start(4))?;
+        self.w
+            .write_all(&(HEADER_LEN - 8 + self.len).to_le_bytes())?;
+        self.w.seek(SeekFrom::Start(HEADER_LEN as u64 - 4))?;
+        self.w.write_all(&self.len.to_le_bytes())?;
+        self.w.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn header_follows_data() {
+        let mut w = WavWriter::new(Cursor::new(vec![]), 44100).unwrap();
+        w.write(&[0.0, 1.0]).unwrap();
+        w.write(&[-2.0]).unwrap();
+        let b = w.into_inner().into_inner();
+        assert_eq!(b.len(), 44 + 6);
+        assert_eq!(&b[4..8], &42u32.to_le_bytes());
+        assert_eq!(&b[24..28], &44100u32.to_le_bytes());
+        assert_eq!(&b[40..44], &6u32.to_le_bytes());
+        assert_eq!(&b[44..], &[0, 0, 0xff, 0x7f, 0x01, 0x80]);
+    }
+}
@@ -0,0 +1,156 @@
+//! blargg's test ROMs, run on the whole console.
+//! The newer ones report through PRG-RAM: $6001-$6003 hold DE B0 61 once the ROM has started, $6000 is $80 while
+//! a test runs, $81 when it wants the reset button pressed, and otherwise the result, 0 for a pass. A message for
+//! people, zero terminated, starts at $6004.
+//!
+//! The ROMs are not in the repository, so these are ignored. Point `NES_TEST_ROMS` at a checkout of
+//! <https://github.com/christopherpow/nes-test-roms> and run `cargo test --test test_roms -- --ignored`.
+use nes::{BusAccess, Cartridge, Cpu, DataBus, Six502};
+use std::error::Error;
+use std::path::PathBuf;
+
+// the longest any of them takes, in seconds of console time
+const TIME_LIMIT: f64 = 30.0;
+
+const RUNNING: u8 = 0x80;
+const RESET: u8 = 0x81;
+const SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+
+// what `cart` left in PRG-RAM when it finished: the result, and the message
+fn run(cart: Cartridge) -> Result<(u8, String), Box<dyn Error>> {
+    let mut cpu = Six502::with_bus(DataBus::console(cart)?);
+    cpu.start()?;
+    let clock = cpu.bus().region().cpu_clock();
+    let limit = (clock * TIME_LIMIT) as u64;
+    // looking once a frame is plenty, and keeps our reads off the open bus the ROM might be testing
+    let frame = (clock / 60.0) as u64;
+    let mut next_look = frame;
+    let mut reset_at = None;
+    while cpu.bus().cycles() < limit {
+        cpu.exec()?;
+        let now = cpu.bus().cycles();
+        if reset_at.is_some_and(|at| now >= at) {
+            reset_at = None;
+            cpu.reset();
+        }
+        if now < next_look {
+            continue;
+        }
+        next_look = now + frame;
+        let bus = cpu.bus_mut();
+        if [0x6001, 0x6002, 0x6003].map(|a| bus.load_u8(a)) != SIGNATURE {
+            continue;
+        }
+        match bus.load_u8(0x6000) {
+            RUNNING => (),
+            // the button wants to be held a while, and the ROM waits for it to let go
+            RESET => {
+                reset_at.get_or_insert(now + (clock / 10.0) as u64);
+            }
+            result => {
+                let text = (0x6004..0x7000).map(|a| bus.load_u8(a)).take_while(|&b| b != 0).collect::<Vec<_>>();
+                return Ok((result, String::from_utf8_lossy(&text).trim().to_string()));
+            }
+        }
+    }
+    Err(format!("still running after {} seconds", TIME_LIMIT).into())
+}
+
+// runs every one of `roms` in `dir` under NES_TEST_ROMS, and fails with those that did not pass. `fix` gets to
+// change what a header says before a ROM goes in
+fn suite(dir: &str, roms: &[&str], fix: fn(&str, &mut Cartridge)) {
+    let root = std::env::var_os("NES_TEST_ROMS").expect("NES_TEST_ROMS should name a checkout of nes-test-roms");
+    let dir = PathBuf::from(root).join(dir);
+    let failed: Vec<String> = roms
+        .iter()
+        .map(|rom| {
+            let cart = Cartridge::open(dir.join(rom)).map(|mut c| {
+                fix(rom, &mut c);
+                c
+            });
+            (rom, cart.and_then(run))
+        })
+        .filter_map(|(rom, result)| match result {
+            Ok((0, _)) => None,
+            Ok((result, text)) => Some(format!("{}: {} {}", rom, result, text)),
+            Err(e) => Some(format!("{}: {}", rom, e)),
+        })
+        .collect();
+    assert!(failed.is_empty(), "\n{}", failed.join("\n"));
+}
+
+#[test]
+#[ignore]
+fn mmc3_test() {
+    // 5 wants the MMC3B's counter and 6 the MMC3A's, which an iNES header has no way to ask for
+    suite(
+        "mmc3_test_2/rom_singles",
+        &["1-clocking.nes", "2-details.nes", "3-A12_clocking.nes", "4-scanline_timing.nes", "5-MMC3.nes", "6-MMC3_alt.nes"],
+        |rom, cart| {
+            if rom == "6-MMC3_alt.nes" {
+                cart.header.submapper = 4;
+            }
+        },
+    );
+}
+
+// the 2005 sprite_hit_tests and sprite_overflow_tests only put their result on screen. These are blargg's later
+// versions of them, which report like the rest
+#[test]
+#[ignore]
+fn ppu_sprite_hit() {
+    suite(
+        "ppu_sprite_hit/rom_singles",
+        &[
+            "01-basics.nes",
+            "02-alignment.nes",
+            "03-corners.nes",
+            "04-flip.nes",
+            "05-left_clip.nes",
+            "06-right_edge.nes",
+            "07-screen_bottom.nes",
+            "08-double_height.nes",
+            "09-timing.nes",
+            "10-timing_order.nes",
+        ],
+        |_, _| (),
+    );
+}
+
+#[test]
+#[ignore]
+fn ppu_sprite_overflow() {
+    suite(
+        "ppu_sprite_overflow/rom_singles",
+        &["01-basics.nes", "02-details.nes", "03-timing.nes", "04-obscure.nes", "05-emulator.nes"],
+        |_, _| (),
+    );
+}
+
+// the length counters, the frame counter and its IRQ. 7 and 8 are the DMC's
+#[test]
+#[ignore]
+fn apu_test() {
+    suite(
+        "apu_test/rom_singles",
+        &["1-len_ctr.nes", "2-len_table.nes", "3-irq_flag.nes", "4-jitter.nes", "5-len_timing.nes", "6-irq_flag_timing.nes"],
+        |_, _| (),
+    );
+}
+
+#[test]
+#[ignore]
+fn apu_test_dmc() {
+    suite("apu_test/rom_singles", &["7-dmc_basics.nes", "8-dmc_rates.nes"], |_, _| ());
+}
+
+// a DMC fetch landing on a read of $2007 or a controller, which then reads twice
+#[test]
+#[ignore]
+fn dmc_dma_during_read4() {
+    suite(
+        "dmc_dma_during_read4",
+        &["dma_2007_read.nes", "dma_2007_write.nes", "dma_4016_read.nes", "double_2007_read.nes", "read_write_2007.nes"],
+        |_, _| (),
+    );
+}